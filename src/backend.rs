@@ -0,0 +1,405 @@
+use std::{
+    fs,
+    io::{self, ErrorKind, IsTerminal, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    os::unix::{fs::PermissionsExt, net::UnixStream, process::ExitStatusExt},
+    path::Path,
+    process::{Command, ExitStatus, Output, Stdio},
+    sync::mpsc,
+    thread,
+    time::Duration,
+};
+
+use ssh2::{Channel, Session};
+use tracing::{debug, trace};
+
+use crate::redact::redact;
+
+
+/// Onde os comandos de um host são executados. Os comandos são de shell e
+/// os mesmos nos dois backends: via SSH no host ou na máquina local.
+pub trait Backend: Send + Sync {
+    /// Executa o comando, com a saída apenas nos logs
+    fn run(&self, command: &str) -> anyhow::Result<()>;
+
+    /// Executa o comando sem ecoá-lo e devolve o stdout
+    fn output(&self, command: &str) -> anyhow::Result<String>;
+
+    /// Grava `size` bytes de `content` em um arquivo do host
+    fn write(&self, path: &str, content: &mut dyn Read, size: u64, mode: i32)
+        -> anyhow::Result<()>;
+
+    /// Executa o comando ligado à saída do ddr (ou ao terminal, se
+    /// interativo) e devolve o código de saída
+    fn attach(&self, command: &str, interactive: bool) -> anyhow::Result<i32>;
+
+    /// Abre uma conexão TCP a partir do host e a fecha em seguida
+    fn probe_tcp(&self, host: &str, port: u16) -> anyhow::Result<()>;
+
+    /// Abre uma conexão com o socket da API do Docker no host
+    fn docker_socket(&self) -> io::Result<Box<dyn Stream>>;
+}
+
+
+/// Socket da API do Docker Engine nos hosts
+pub const DOCKER_SOCKET: &str = "/var/run/docker.sock";
+
+
+/// Conexão de leitura e escrita, como um canal SSH ou um socket unix
+pub trait Stream: Read + Write {}
+
+impl<T: Read + Write> Stream for T {}
+
+
+pub struct SshBackend {
+    session: Session,
+}
+
+
+impl SshBackend {
+    pub fn new(session: Session) -> Self {
+        SshBackend { session }
+    }
+}
+
+
+impl Backend for SshBackend {
+    fn run(&self, command: &str) -> anyhow::Result<()> {
+        debug!("Executando comando remoto: {}", redact(command));
+
+        let mut channel = self.session.channel_session()?;
+        channel.exec(command)?;
+
+        // stdout
+        let mut stdout = Vec::new();
+        channel.read_to_end(&mut stdout)?;
+        trace_output("remote stdout", &stdout);
+
+        // stderr
+        let mut stderr = Vec::new();
+        channel.stderr().read_to_end(&mut stderr)?;
+        trace_output("remote stderr", &stderr);
+
+        channel.wait_close()?;
+        let exit_status = channel.exit_status()?;
+        if exit_status != 0 {
+            anyhow::bail!(
+                "Comando remoto falhou ({exit_status}): {}\n{}",
+                redact(command),
+                redact(String::from_utf8_lossy(&stderr).trim())
+            );
+        }
+
+        Ok(())
+    }
+
+    fn output(&self, command: &str) -> anyhow::Result<String> {
+        let mut channel = self.session.channel_session()?;
+        channel.exec(command)?;
+
+        let mut stdout = String::new();
+        channel.read_to_string(&mut stdout)?;
+
+        let mut stderr = String::new();
+        channel.stderr().read_to_string(&mut stderr)?;
+
+        channel.wait_close()?;
+        let exit_status = channel.exit_status()?;
+        if exit_status != 0 {
+            anyhow::bail!(
+                "Comando remoto falhou ({exit_status}): {}\n{}",
+                redact(command),
+                redact(stderr.trim())
+            );
+        }
+
+        Ok(stdout)
+    }
+
+    fn write(
+        &self,
+        path: &str,
+        content: &mut dyn Read,
+        size: u64,
+        mode: i32,
+    ) -> anyhow::Result<()> {
+        let mut remote_file = self.session.scp_send(Path::new(path), mode, size, None)?;
+
+        std::io::copy(content, &mut remote_file)?;
+        remote_file.send_eof()?;
+        remote_file.wait_eof()?;
+        remote_file.close()?;
+        remote_file.wait_close()?;
+        Ok(())
+    }
+
+    fn attach(&self, command: &str, interactive: bool) -> anyhow::Result<i32> {
+        let mut channel: Channel = self.session.channel_session()?;
+
+        if interactive {
+            let (cols, rows) = terminal_size();
+            channel.request_pty("xterm-256color", None, Some((cols, rows, 0, 0)))?;
+            channel.exec(command)?;
+
+            run_interactive(&self.session, &mut channel)?;
+        } else {
+            channel.exec(command)?;
+
+            std::io::copy(&mut channel, &mut std::io::stdout())?;
+            std::io::copy(&mut channel.stderr(), &mut std::io::stderr())?;
+        }
+
+        channel.wait_close()?;
+        Ok(channel.exit_status()?)
+    }
+
+    // A conexão é aberta pelo servidor SSH, no próprio host
+    fn probe_tcp(&self, host: &str, port: u16) -> anyhow::Result<()> {
+        let mut channel = self.session.channel_direct_tcpip(host, port, None)?;
+        channel.close().ok();
+        Ok(())
+    }
+
+    // Encaminhado pelo servidor SSH, que precisa permitir streamlocal
+    fn docker_socket(&self) -> io::Result<Box<dyn Stream>> {
+        Ok(Box::new(self.session.channel_direct_streamlocal(DOCKER_SOCKET, None)?))
+    }
+}
+
+
+/// Executa os comandos com `sh -c` na máquina local
+pub struct LocalBackend;
+
+
+impl Backend for LocalBackend {
+    fn run(&self, command: &str) -> anyhow::Result<()> {
+        debug!("Executando comando local: {}", redact(command));
+
+        let output: Output = shell(command).stdin(Stdio::null()).output()?;
+        trace_output("local stdout", &output.stdout);
+        trace_output("local stderr", &output.stderr);
+
+        if !output.status.success() {
+            anyhow::bail!(
+                "Comando local falhou ({}): {}\n{}",
+                exit_code(output.status),
+                redact(command),
+                redact(String::from_utf8_lossy(&output.stderr).trim())
+            );
+        }
+
+        Ok(())
+    }
+
+    fn output(&self, command: &str) -> anyhow::Result<String> {
+        let output: Output = shell(command).stdin(Stdio::null()).output()?;
+
+        if !output.status.success() {
+            anyhow::bail!(
+                "Comando local falhou ({}): {}\n{}",
+                exit_code(output.status),
+                redact(command),
+                redact(String::from_utf8_lossy(&output.stderr).trim())
+            );
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    fn write(
+        &self,
+        path: &str,
+        content: &mut dyn Read,
+        _size: u64,
+        mode: i32,
+    ) -> anyhow::Result<()> {
+        let mut file = fs::File::create(path)?;
+        std::io::copy(content, &mut file)?;
+        file.set_permissions(fs::Permissions::from_mode(mode as u32))?;
+        Ok(())
+    }
+
+    // O terminal local é herdado pelo comando, inclusive no modo interativo
+    fn attach(&self, command: &str, _interactive: bool) -> anyhow::Result<i32> {
+        Ok(exit_code(shell(command).status()?))
+    }
+
+    fn probe_tcp(&self, host: &str, port: u16) -> anyhow::Result<()> {
+        let connected = (host, port)
+            .to_socket_addrs()?
+            .any(|address| TcpStream::connect(address).is_ok());
+
+        if !connected {
+            anyhow::bail!("conexão recusada");
+        }
+
+        Ok(())
+    }
+
+    fn docker_socket(&self) -> io::Result<Box<dyn Stream>> {
+        Ok(Box::new(UnixStream::connect(DOCKER_SOCKET)?))
+    }
+}
+
+
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+
+/// Código de saída, ou 128 + o sinal que terminou o processo, como no shell
+fn exit_code(status: ExitStatus) -> i32 {
+    status
+        .code()
+        .or_else(|| status.signal().map(|signal| 128 + signal))
+        .unwrap_or(1)
+}
+
+
+fn trace_output(stream: &str, output: &[u8]) {
+    let output = String::from_utf8_lossy(output);
+    if !output.trim().is_empty() {
+        trace!("[{stream}] {}", redact(output.trim_end()));
+    }
+}
+
+
+/// Encaminha o terminal local para o canal até o comando remoto terminar
+fn run_interactive(session: &Session, channel: &mut Channel) -> anyhow::Result<()> {
+    let _raw_mode = RawMode::enable()?;
+
+    // A leitura do stdin bloqueia, então fica em uma thread separada
+    let (sender, receiver) = mpsc::channel::<Vec<u8>>();
+    thread::spawn(move || {
+        let mut stdin = std::io::stdin();
+        let mut buffer = [0u8; 1024];
+        while let Ok(read) = stdin.read(&mut buffer) {
+            if read == 0 || sender.send(buffer[..read].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+
+    session.set_blocking(false);
+
+    let mut stdout = std::io::stdout();
+    let mut pending_input: Vec<u8> = Vec::new();
+    let mut last_size: (u32, u32) = terminal_size();
+    let mut buffer = [0u8; 8192];
+
+    loop {
+        let mut progressed = false;
+
+        while let Ok(input) = receiver.try_recv() {
+            pending_input.extend(input);
+        }
+
+        if !pending_input.is_empty() {
+            match channel.write(&pending_input) {
+                Ok(written) => {
+                    pending_input.drain(..written);
+                    progressed = true;
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        let size = terminal_size();
+        if size != last_size
+            && channel.request_pty_size(size.0, size.1, None, None).is_ok() {
+            last_size = size;
+        }
+
+        match channel.read(&mut buffer) {
+            Ok(0) if channel.eof() => break,
+            Ok(read) => {
+                stdout.write_all(&buffer[..read])?;
+                stdout.flush()?;
+                progressed |= read > 0;
+            }
+            Err(err) if err.kind() == ErrorKind::WouldBlock => {}
+            Err(err) => return Err(err.into()),
+        }
+
+        if !progressed {
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    session.set_blocking(true);
+    Ok(())
+}
+
+
+/// Colunas e linhas do terminal local (80x24 se não for um terminal)
+fn terminal_size() -> (u32, u32) {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+
+    // SAFETY: TIOCGWINSZ só escreve na struct winsize informada
+    let result = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+    if result != 0 || size.ws_col == 0 {
+        return (80, 24);
+    }
+
+    (size.ws_col as u32, size.ws_row as u32)
+}
+
+
+/// Coloca o terminal em modo raw e restaura o modo original ao sair de escopo
+struct RawMode {
+    original: libc::termios,
+}
+
+impl RawMode {
+    fn enable() -> anyhow::Result<Option<Self>> {
+        if !std::io::stdin().is_terminal() {
+            return Ok(None);
+        }
+
+        let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+
+        // SAFETY: tcgetattr/tcsetattr só acessam a struct termios informada
+        unsafe {
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+                anyhow::bail!("Não foi possível ler o modo do terminal");
+            }
+
+            let original = termios;
+            libc::cfmakeraw(&mut termios);
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) != 0 {
+                anyhow::bail!("Não foi possível colocar o terminal em modo raw");
+            }
+
+            Ok(Some(RawMode { original }))
+        }
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        // SAFETY: restaura o termios lido em `enable`
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failed_local_commands_report_stderr() {
+        let err = LocalBackend.run("echo ok; echo 'no such image' >&2; exit 3").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Comando local falhou (3): echo ok; echo 'no such image' >&2; exit 3\nno such image"
+        );
+
+        assert!(LocalBackend.run("echo aviso >&2").is_ok());
+    }
+}
@@ -0,0 +1,276 @@
+use std::{collections::HashSet, time::{Duration, Instant}};
+
+use serde_yaml::{from_value, Mapping, Value};
+use tracing::{error, info, info_span};
+
+use crate::{
+    hosts::{Inventory, Sessions},
+    instances::{group_services, RESERVED_GROUPS},
+    models::{DeployFilter, ServiceConfig},
+    networks::handle_networks,
+    redact::redact,
+    report::{write_reports, DeployRecords, GroupOutcome, GroupStatus},
+    services::{handle_group, service_names},
+    volumes::handle_volumes,
+};
+
+
+/// Grupos reservados que também são implantados, antes dos grupos de serviços
+const INFRA_GROUPS: [&str; 2] = ["networks", "volumes"];
+
+
+pub fn handle_deploy(
+    inventory: &Inventory,
+    deploy_map: &Mapping,
+    group_names: &[String],
+    all: bool,
+    filter: &DeployFilter,
+    report_paths: &[String],
+    dry_run: bool,
+) -> anyhow::Result<()> {
+
+    let deploy_started = Instant::now();
+    let group_names: Vec<String> = if all {
+        deploy_map
+            .keys()
+            .filter_map(Value::as_str)
+            .filter(|name| !RESERVED_GROUPS.contains(name) || INFRA_GROUPS.contains(name))
+            .map(str::to_owned)
+            .collect()
+    } else {
+        group_names.to_vec()
+    };
+
+    for group_name in &group_names {
+        if deploy_map.get(group_name.as_str()).is_none() {
+            anyhow::bail!("Grupo não encontrado: {group_name}");
+        }
+    }
+
+    let ordered_groups: Vec<String> = resolve_group_order(deploy_map, &group_names)?;
+
+    let mut sessions: Sessions = Sessions::new(inventory);
+    let mut outcomes: Vec<GroupOutcome> = Vec::new();
+    let mut records: DeployRecords = DeployRecords::default();
+    let mut failed: bool = false;
+
+    for group_name in ordered_groups {
+        if failed {
+            outcomes.push(GroupOutcome {
+                group_name,
+                status: GroupStatus::Skipped,
+                duration: Duration::ZERO,
+            });
+            continue;
+        }
+
+        let span = info_span!("group", group = %group_name);
+        let _entered = span.enter();
+
+        info!("Deploy do grupo {group_name}");
+        let started = Instant::now();
+        let result = deploy_group(
+            &mut sessions,
+            &group_name,
+            deploy_map,
+            filter,
+            &mut records,
+            dry_run
+        );
+
+        let status = match result {
+            Ok(()) => GroupStatus::Ok,
+            Err(err) => {
+                failed = true;
+                error!("O deploy do grupo {group_name} falhou: {}", redact(&format!("{err:#}")));
+                GroupStatus::Failed(format!("{err:#}"))
+            }
+        };
+
+        outcomes.push(GroupOutcome {
+            group_name,
+            status,
+            duration: started.elapsed(),
+        });
+    }
+
+    print_summary(&outcomes);
+    write_reports(report_paths, &outcomes, &records, deploy_started.elapsed(), dry_run)?;
+
+    if failed {
+        anyhow::bail!("O deploy falhou");
+    }
+
+    Ok(())
+}
+
+
+fn deploy_group(
+    sessions: &mut Sessions,
+    group_name: &str,
+    deploy_map: &Mapping,
+    filter: &DeployFilter,
+    records: &mut DeployRecords,
+    dry_run: bool,
+) -> anyhow::Result<()> {
+    match group_name {
+        "define" | "hosts" => Ok(()),
+        "networks" => handle_networks(sessions, deploy_map, dry_run),
+        "volumes" => handle_volumes(sessions, deploy_map, dry_run),
+        _ => handle_group(sessions, group_name, deploy_map, filter, records, dry_run),
+    }
+}
+
+
+/// Ordena os grupos: `networks` e `volumes` primeiro, depois os grupos de
+/// serviços de forma que um grupo venha depois dos grupos dos quais algum
+/// serviço seu depende (`depends_on`). Empates mantêm a ordem recebida.
+pub fn resolve_group_order(
+    deploy_map: &Mapping,
+    group_names: &[String],
+) -> anyhow::Result<Vec<String>> {
+
+    let mut ordered: Vec<String> = Vec::new();
+    for special in INFRA_GROUPS {
+        if group_names.iter().any(|name| name == special) {
+            ordered.push(special.to_string());
+        }
+    }
+
+    let mut pending: Vec<(String, HashSet<String>, HashSet<String>)> = Vec::new();
+    for group_name in group_names {
+        if RESERVED_GROUPS.contains(&group_name.as_str())
+            || pending.iter().any(|(name, _, _)| name == group_name)
+        {
+            continue;
+        }
+
+        let services: Mapping = deploy_map
+            .get(group_name.as_str())
+            .and_then(Value::as_mapping)
+            .map(group_services)
+            .ok_or_else(|| anyhow::anyhow!("Grupo {group_name} não é um mapping"))?;
+
+        let provides: HashSet<String> = service_names(&services)?;
+        let mut requires: HashSet<String> = HashSet::new();
+        for service_config in services.values() {
+            let service: ServiceConfig = from_value(service_config.clone())?;
+            requires.extend(service.depends_on.unwrap_or_default());
+        }
+
+        pending.push((group_name.clone(), provides, requires));
+    }
+
+    while !pending.is_empty() {
+        let ready = pending.iter().position(|(name, _, requires)| {
+            !pending.iter().any(|(other, provides, _)| {
+                other != name && !requires.is_disjoint(provides)
+            })
+        });
+
+        match ready {
+            Some(index) => ordered.push(pending.remove(index).0),
+            None => {
+                let names: Vec<&str> = pending.iter().map(|(name, _, _)| name.as_str()).collect();
+                anyhow::bail!(
+                    "Dependência cíclica entre os grupos: {}",
+                    names.join(", ")
+                );
+            }
+        }
+    }
+
+    Ok(ordered)
+}
+
+
+fn print_summary(outcomes: &[GroupOutcome]) {
+    println!("================= RESUMO DO DEPLOY =================");
+    for outcome in outcomes {
+        let seconds = outcome.duration.as_secs_f64();
+        match &outcome.status {
+            GroupStatus::Ok => {
+                println!("{:<20} ok        {:.1}s", outcome.group_name, seconds);
+            }
+            GroupStatus::Failed(err) => {
+                println!(
+                    "{:<20} falhou    {:.1}s  {}",
+                    outcome.group_name,
+                    seconds,
+                    redact(err)
+                );
+            }
+            GroupStatus::Skipped => {
+                println!("{:<20} ignorado", outcome.group_name);
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(deploy: &str, groups: &[&str]) -> anyhow::Result<Vec<String>> {
+        let deploy_map: Mapping = serde_yaml::from_str(deploy).expect("deploy inválido");
+        let groups: Vec<String> = groups.iter().map(|group| group.to_string()).collect();
+        resolve_group_order(&deploy_map, &groups)
+    }
+
+    const DEPLOY: &str = "
+networks: {net1: {}}
+volumes: {data: {}}
+web:
+  front: {image: front, depends_on: [api], instances: {front1: {}}}
+backend:
+  api: {image: api, depends_on: [db], instances: {api1: {}}}
+database:
+  db: {image: db, instances: {db1: {}}}
+";
+
+    #[test]
+    fn groups_follow_the_dependencies_between_their_services() {
+        let ordered = order(DEPLOY, &["web", "backend", "database"]).unwrap();
+        assert_eq!(ordered, ["database", "backend", "web"]);
+    }
+
+    #[test]
+    fn networks_and_volumes_come_first() {
+        let ordered = order(DEPLOY, &["database", "volumes", "networks"]).unwrap();
+        assert_eq!(ordered, ["networks", "volumes", "database"]);
+    }
+
+    #[test]
+    fn independent_groups_keep_the_given_order() {
+        let ordered = order(DEPLOY, &["database", "networks"]).unwrap();
+        assert_eq!(ordered, ["networks", "database"]);
+
+        let deploy = "
+a:
+  one: {image: one, instances: {one1: {}}}
+b:
+  two: {image: two, instances: {two1: {}}}
+";
+        assert_eq!(order(deploy, &["b", "a"]).unwrap(), ["b", "a"]);
+    }
+
+    #[test]
+    fn dependencies_outside_the_selection_are_ignored() {
+        let ordered = order(DEPLOY, &["web", "backend"]).unwrap();
+        assert_eq!(ordered, ["backend", "web"]);
+    }
+
+    #[test]
+    fn cyclic_groups_are_an_error() {
+        let deploy = "
+a:
+  one: {image: one, depends_on: [two], instances: {one1: {}}}
+b:
+  two: {image: two, depends_on: [one], instances: {two1: {}}}
+";
+        let err = order(deploy, &["a", "b"]).unwrap_err().to_string();
+        assert!(err.contains("cíclica"), "{err}");
+        assert!(err.contains('a') && err.contains('b'), "{err}");
+    }
+}
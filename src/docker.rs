@@ -0,0 +1,630 @@
+use std::{
+    fmt,
+    io::{self, Read},
+    path::Path,
+};
+
+use serde_json::{json, Map, Value};
+use shell_escape::escape;
+use tracing::debug;
+
+use crate::{
+    backend::{Backend, Stream},
+    models::{parse_duration, ContainerConfig},
+    secrets::instance_secrets_bind,
+    services::{build_health_cmd, CONFIG_LABEL},
+    utils::{previous_container, run_remote_output},
+};
+
+
+/// Versão da API do Docker Engine usada nas chamadas (Docker 20.10+)
+const API_VERSION: &str = "v1.41";
+
+
+/// Erros das chamadas à API do Docker
+#[derive(Debug)]
+pub enum DockerError {
+    /// Não foi possível falar com o socket do Docker
+    Connection(io::Error),
+    /// A resposta não é HTTP válido
+    Protocol(String),
+    /// Contêiner ou imagem inexistente (404)
+    NotFound(String),
+    /// Conflito com o estado atual, como um nome já em uso (409)
+    Conflict(String),
+    /// Qualquer outra resposta de erro da API
+    Api { status: u16, message: String },
+}
+
+
+impl fmt::Display for DockerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DockerError::Connection(_) => write!(f, "Sem conexão com a API do Docker"),
+            DockerError::Protocol(message) => write!(f, "Resposta inválida do Docker: {message}"),
+            DockerError::NotFound(message) => write!(f, "Não encontrado no Docker: {message}"),
+            DockerError::Conflict(message) => write!(f, "Conflito no Docker: {message}"),
+            DockerError::Api { status, message } => {
+                write!(f, "Erro da API do Docker ({status}): {message}")
+            }
+        }
+    }
+}
+
+
+impl std::error::Error for DockerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DockerError::Connection(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+
+impl From<io::Error> for DockerError {
+    fn from(err: io::Error) -> Self {
+        DockerError::Connection(err)
+    }
+}
+
+
+/// Corpo de uma requisição
+enum Body<'a> {
+    Empty,
+    Json(&'a Value),
+    /// Arquivo tar com o tamanho informado, enviado sem ser lido em memória
+    Tar(&'a mut dyn Read, u64),
+}
+
+
+struct Response {
+    status: u16,
+    body: Vec<u8>,
+}
+
+
+/// Cliente da API do Docker Engine de um host, pelo socket unix do Docker
+/// (encaminhado pelo SSH ou local). Cada chamada usa uma conexão nova.
+pub struct DockerApi<'a> {
+    backend: &'a dyn Backend,
+}
+
+
+impl<'a> DockerApi<'a> {
+    pub fn new(backend: &'a dyn Backend) -> Self {
+        DockerApi { backend }
+    }
+
+    /// Imagem, hash da label `ddr.config` e se o contêiner está rodando, ou
+    /// `None` se ele não existir
+    pub fn inspect(&self, name: &str) -> Result<Option<(String, String, bool)>, DockerError> {
+        let response = match self.call("GET", &format!("/containers/{name}/json"), Body::Empty) {
+            Err(DockerError::NotFound(_)) => return Ok(None),
+            response => response?,
+        };
+
+        let container: Value = parse_json(&response.body)?;
+        let image: &str = container["Image"]
+            .as_str()
+            .ok_or_else(|| DockerError::Protocol(format!("contêiner {name} sem imagem")))?;
+        let config_hash: &str = container["Config"]["Labels"][CONFIG_LABEL]
+            .as_str()
+            .unwrap_or_default();
+
+        let running: bool = container["State"]["Running"].as_bool().unwrap_or(false);
+
+        Ok(Some((image.to_owned(), config_hash.to_owned(), running)))
+    }
+
+    /// ID da imagem, ou `None` se ela não existir
+    pub fn image_id(&self, image: &str) -> Result<Option<String>, DockerError> {
+        let response = match self.call("GET", &format!("/images/{image}/json"), Body::Empty) {
+            Err(DockerError::NotFound(_)) => return Ok(None),
+            response => response?,
+        };
+
+        let image: Value = parse_json(&response.body)?;
+        Ok(image["Id"].as_str().map(str::to_owned))
+    }
+
+    pub fn exists(&self, name: &str) -> Result<bool, DockerError> {
+        Ok(self.inspect(name)?.is_some())
+    }
+
+    pub fn create(&self, name: &str, spec: &Value) -> Result<(), DockerError> {
+        self.call("POST", &format!("/containers/create?name={name}"), Body::Json(spec))?;
+        Ok(())
+    }
+
+    pub fn start(&self, name: &str) -> Result<(), DockerError> {
+        self.call("POST", &format!("/containers/{name}/start"), Body::Empty)?;
+        Ok(())
+    }
+
+    pub fn stop(&self, name: &str) -> Result<(), DockerError> {
+        self.call("POST", &format!("/containers/{name}/stop"), Body::Empty)?;
+        Ok(())
+    }
+
+    pub fn rename(&self, name: &str, new_name: &str) -> Result<(), DockerError> {
+        self.call("POST", &format!("/containers/{name}/rename?name={new_name}"), Body::Empty)?;
+        Ok(())
+    }
+
+    /// Remove o contêiner à força; não é erro se ele não existir
+    pub fn remove(&self, name: &str) -> Result<(), DockerError> {
+        match self.call("DELETE", &format!("/containers/{name}?force=true"), Body::Empty) {
+            Ok(_) | Err(DockerError::NotFound(_)) => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Carrega no Docker do host uma imagem exportada com `docker save`
+    pub fn load(&self, tar: &mut dyn Read, size: u64) -> Result<(), DockerError> {
+        let response = self.call("POST", "/images/load?quiet=1", Body::Tar(tar, size))?;
+
+        // Os erros do load vêm como mensagens JSON em uma resposta 200
+        for line in String::from_utf8_lossy(&response.body).lines() {
+            let Ok(message) = serde_json::from_str::<Value>(line) else { continue };
+            if let Some(error) = message["error"].as_str() {
+                return Err(DockerError::Api { status: response.status, message: error.into() });
+            }
+            if let Some(stream) = message["stream"].as_str() {
+                debug!("Docker load: {}", stream.trim());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Recria o contêiner a partir de `spec`. Com `keep_previous`, o
+    /// contêiner atual é parado e renomeado em vez de removido, para um
+    /// possível rollback.
+    pub fn replace(&self, name: &str, spec: &Value, keep_previous: bool) -> anyhow::Result<()> {
+        if keep_previous {
+            let previous: String = previous_container(name);
+            self.remove(&previous)?;
+            if self.exists(name)? {
+                self.stop(name)?;
+                self.rename(name, &previous)?;
+            }
+        } else {
+            self.remove(name)?;
+        }
+
+        self.create(name, spec)?;
+        self.start(name)?;
+
+        Ok(())
+    }
+
+    /// Remove o contêiner novo e volta a rodar o anterior, se houver um
+    pub fn rollback(&self, name: &str) -> anyhow::Result<()> {
+        let previous: String = previous_container(name);
+        self.remove(name)?;
+        if self.exists(&previous)? {
+            self.rename(&previous, name)?;
+            self.start(name)?;
+        }
+
+        Ok(())
+    }
+
+    /// Remove o contêiner anterior guardado para rollback
+    pub fn discard_previous(&self, name: &str) -> anyhow::Result<()> {
+        Ok(self.remove(&previous_container(name))?)
+    }
+
+    fn call(&self, method: &str, path: &str, body: Body) -> Result<Response, DockerError> {
+        debug!("Docker API: {method} {path}");
+
+        let mut stream: Box<dyn Stream> = self.backend.docker_socket()?;
+        let response: Response = send(stream.as_mut(), method, path, body)?;
+
+        if response.status >= 400 {
+            let message: String = serde_json::from_slice::<Value>(&response.body)
+                .ok()
+                .and_then(|error| error["message"].as_str().map(str::to_owned))
+                .unwrap_or_else(|| String::from_utf8_lossy(&response.body).trim().to_owned());
+
+            return Err(match response.status {
+                404 => DockerError::NotFound(message),
+                409 => DockerError::Conflict(message),
+                status => DockerError::Api { status, message },
+            });
+        }
+
+        Ok(response)
+    }
+}
+
+
+/// Envia a requisição HTTP/1.1 e lê a resposta até o Docker fechar a conexão
+fn send(
+    stream: &mut dyn Stream,
+    method: &str,
+    path: &str,
+    body: Body,
+) -> Result<Response, DockerError> {
+
+    let mut head: String = format!(
+        "{method} /{API_VERSION}{path} HTTP/1.1\r\nHost: docker\r\nConnection: close\r\n"
+    );
+
+    match body {
+        Body::Empty => {
+            head += "Content-Length: 0\r\n\r\n";
+            stream.write_all(head.as_bytes())?;
+        }
+        Body::Json(value) => {
+            let content: Vec<u8> = serde_json::to_vec(value)
+                .map_err(|err| DockerError::Protocol(err.to_string()))?;
+            head += &format!(
+                "Content-Type: application/json\r\nContent-Length: {}\r\n\r\n",
+                content.len()
+            );
+            stream.write_all(head.as_bytes())?;
+            stream.write_all(&content)?;
+        }
+        Body::Tar(content, size) => {
+            head += &format!("Content-Type: application/x-tar\r\nContent-Length: {size}\r\n\r\n");
+            stream.write_all(head.as_bytes())?;
+            io::copy(&mut content.take(size), stream)?;
+        }
+    }
+    stream.flush()?;
+
+    let mut raw: Vec<u8> = Vec::new();
+    stream.read_to_end(&mut raw)?;
+
+    parse_response(&raw)
+}
+
+
+fn parse_response(raw: &[u8]) -> Result<Response, DockerError> {
+    let invalid = |message: &str| DockerError::Protocol(message.to_owned());
+
+    let split: usize = raw
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .ok_or_else(|| invalid("resposta sem cabeçalhos"))?;
+    let head: String = String::from_utf8_lossy(&raw[..split]).into_owned();
+    let body: &[u8] = &raw[split + 4..];
+
+    let mut lines = head.lines();
+    let status: u16 = lines
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| invalid("linha de status inválida"))?;
+
+    let chunked: bool = lines.any(|line| {
+        line.split_once(':').is_some_and(|(name, value)| {
+            name.trim().eq_ignore_ascii_case("transfer-encoding")
+                && value.trim().eq_ignore_ascii_case("chunked")
+        })
+    });
+
+    let body: Vec<u8> = if chunked { dechunk(body)? } else { body.to_vec() };
+    Ok(Response { status, body })
+}
+
+
+/// Junta os pedaços de um corpo com `Transfer-Encoding: chunked`
+fn dechunk(mut raw: &[u8]) -> Result<Vec<u8>, DockerError> {
+    let invalid = || DockerError::Protocol("corpo chunked inválido".to_owned());
+
+    let mut body: Vec<u8> = Vec::new();
+    loop {
+        let line_end: usize = raw
+            .windows(2)
+            .position(|window| window == b"\r\n")
+            .ok_or_else(invalid)?;
+        let size_line: String = String::from_utf8_lossy(&raw[..line_end]).into_owned();
+        let size_hex: &str = size_line.split(';').next().unwrap_or_default().trim();
+        let size: usize = usize::from_str_radix(size_hex, 16).map_err(|_| invalid())?;
+
+        raw = &raw[line_end + 2..];
+        if size == 0 {
+            return Ok(body);
+        }
+        if raw.len() < size {
+            return Err(invalid());
+        }
+
+        body.extend_from_slice(&raw[..size]);
+        raw = raw.get(size + 2..).ok_or_else(invalid)?;
+    }
+}
+
+
+fn parse_json(body: &[u8]) -> Result<Value, DockerError> {
+    serde_json::from_slice(body).map_err(|err| DockerError::Protocol(err.to_string()))
+}
+
+
+/// Corpo do `POST /containers/create` com as mesmas opções que o
+/// `docker run` da instância. Os env_file são lidos no host, a partir de
+/// `from_dir`, e os bind mounts relativos são resolvidos a partir dele.
+pub fn container_spec(
+    session: &dyn Backend,
+    from_dir: &str,
+    container_config: &ContainerConfig,
+    image_name: &str,
+    config_hash: &str,
+    secrets_of: Option<&str>,
+) -> anyhow::Result<Value> {
+
+    let mut spec: Map<String, Value> = Map::new();
+    let mut host_config: Map<String, Value> = Map::new();
+
+    spec.insert("Image".into(), json!(image_name));
+
+    if let Some(ref command) = container_config.command {
+        let args: Vec<String> = shlex::split(command)
+            .ok_or_else(|| anyhow::anyhow!("Comando inválido: {command}"))?;
+        spec.insert("Cmd".into(), json!(args));
+    }
+
+    let mut env: Vec<String> = Vec::new();
+    for env_file in container_config.env_file.iter().flatten() {
+        let content: String = run_remote_output(
+            session,
+            &format!(
+                "cd {} && cat {}",
+                escape(from_dir.into()),
+                escape(env_file.as_str().into())
+            ),
+        )?;
+        env.extend(
+            content
+                .lines()
+                .map(str::trim_start)
+                .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+                .map(str::to_owned),
+        );
+    }
+    env.extend(container_config.environment.iter().flatten().cloned());
+
+    // Como no `docker run`, uma variável sem `=` vem do ambiente do host e
+    // é omitida se ele não a definir
+    let mut resolved_env: Vec<String> = Vec::with_capacity(env.len());
+    for variable in env {
+        if variable.contains('=') {
+            resolved_env.push(variable);
+        } else if let Some(value) = host_variable(session, variable.trim())? {
+            resolved_env.push(format!("{}={value}", variable.trim()));
+        }
+    }
+    let env: Vec<String> = resolved_env;
+    if !env.is_empty() {
+        spec.insert("Env".into(), json!(env));
+    }
+
+    spec.insert("Labels".into(), json!({ CONFIG_LABEL: config_hash }));
+
+    if let Some(ref network) = container_config.network_mode {
+        host_config.insert("NetworkMode".into(), json!(network));
+    }
+
+    if let Some(ref restart) = container_config.restart {
+        let (name, retries) = restart.split_once(':').unwrap_or((restart, "0"));
+        let retries: u32 = retries
+            .parse()
+            .map_err(|_| anyhow::anyhow!("restart inválido: {restart}"))?;
+        host_config.insert(
+            "RestartPolicy".into(),
+            json!({ "Name": name, "MaximumRetryCount": retries }),
+        );
+    }
+
+    let mut binds: Vec<String> = Vec::new();
+    let mut anonymous: Map<String, Value> = Map::new();
+    for volume in container_config.volumes.iter().flatten() {
+        match volume.split_once(':') {
+            None => {
+                anonymous.insert(volume.clone(), json!({}));
+            }
+            Some((source, rest)) if source.starts_with('.') => {
+                let source = Path::new(from_dir).join(source.trim_start_matches("./"));
+                binds.push(format!("{}:{rest}", source.display()));
+            }
+            Some(_) => binds.push(volume.clone()),
+        }
+    }
+    if let Some(instance_name) = secrets_of {
+        binds.push(instance_secrets_bind(instance_name));
+    }
+    if !binds.is_empty() {
+        host_config.insert("Binds".into(), json!(binds));
+    }
+    if !anonymous.is_empty() {
+        spec.insert("Volumes".into(), Value::Object(anonymous));
+    }
+
+    let mut exposed: Map<String, Value> = Map::new();
+    let mut bindings: Map<String, Value> = Map::new();
+    for port in container_config.ports.iter().flatten() {
+        let (container_port, host_ip, host_port) = parse_port(port)?;
+        exposed.insert(container_port.clone(), json!({}));
+
+        let binding = json!({ "HostIp": host_ip, "HostPort": host_port });
+        match bindings.get_mut(&container_port).and_then(Value::as_array_mut) {
+            Some(existing) => existing.push(binding),
+            None => {
+                bindings.insert(container_port, json!([binding]));
+            }
+        }
+    }
+    if !exposed.is_empty() {
+        spec.insert("ExposedPorts".into(), Value::Object(exposed));
+        host_config.insert("PortBindings".into(), Value::Object(bindings));
+    }
+
+    if let Some(ref hc) = container_config.healthcheck {
+        let mut healthcheck: Map<String, Value> = Map::new();
+        let cmd_string: String = build_health_cmd(hc);
+        if !cmd_string.is_empty() {
+            healthcheck.insert("Test".into(), json!(["CMD-SHELL", cmd_string]));
+        }
+        for (field, duration) in [("Interval", &hc.interval), ("Timeout", &hc.timeout)] {
+            if let Some(duration) = duration {
+                let nanos: u128 = parse_duration(duration)
+                    .ok_or_else(|| anyhow::anyhow!("duração inválida: {duration}"))?
+                    .as_nanos();
+                healthcheck.insert(field.into(), json!(nanos as u64));
+            }
+        }
+        if let Some(retries) = hc.retries {
+            healthcheck.insert("Retries".into(), json!(retries));
+        }
+        spec.insert("Healthcheck".into(), Value::Object(healthcheck));
+    }
+
+    spec.insert("HostConfig".into(), Value::Object(host_config));
+    Ok(Value::Object(spec))
+}
+
+
+/// Valor da variável no ambiente dos comandos no host, ou `None` se ela
+/// não estiver definida
+fn host_variable(session: &dyn Backend, name: &str) -> anyhow::Result<Option<String>> {
+    let output: String = run_remote_output(
+        session,
+        &format!("printenv {} || true", escape(name.into())),
+    )?;
+
+    // O printenv termina o valor com uma quebra de linha, mesmo se vazio
+    Ok(output.strip_suffix('\n').map(str::to_owned))
+}
+
+
+/// Lê `[ip:][porta_host:]porta[/protocolo]`, como no `docker run -p`, e
+/// devolve a porta do contêiner (`80/tcp`), o IP e a porta do host
+fn parse_port(port: &str) -> anyhow::Result<(String, String, String)> {
+    if port.contains('-') {
+        anyhow::bail!("Faixas de portas não são suportadas pela API do Docker: {port}");
+    }
+
+    let (mapping, protocol) = port.split_once('/').unwrap_or((port, "tcp"));
+    let parts: Vec<&str> = mapping.rsplitn(3, ':').collect();
+    let (container_port, host_port, host_ip) = match parts[..] {
+        [container] => (container, "", ""),
+        [container, host] => (container, host, ""),
+        [container, host, ip] => (container, host, ip),
+        _ => anyhow::bail!("Porta inválida: {port}"),
+    };
+
+    let valid: bool = container_port.parse::<u16>().is_ok()
+        && (host_port.is_empty() || host_port.parse::<u16>().is_ok())
+        && ["tcp", "udp", "sctp"].contains(&protocol);
+    if !valid {
+        anyhow::bail!("Porta inválida: {port}");
+    }
+
+    Ok((format!("{container_port}/{protocol}"), host_ip.to_owned(), host_port.to_owned()))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use super::*;
+    use crate::backend::LocalBackend;
+
+    fn port(text: &str) -> (String, String, String) {
+        parse_port(text).unwrap()
+    }
+
+    fn owned(parts: (&str, &str, &str)) -> (String, String, String) {
+        (parts.0.to_owned(), parts.1.to_owned(), parts.2.to_owned())
+    }
+
+    #[test]
+    fn ports_are_parsed_like_docker_run() {
+        assert_eq!(port("80"), owned(("80/tcp", "", "")));
+        assert_eq!(port("8080:80"), owned(("80/tcp", "", "8080")));
+        assert_eq!(port("127.0.0.1:8080:80"), owned(("80/tcp", "127.0.0.1", "8080")));
+        assert_eq!(port("127.0.0.1::80/udp"), owned(("80/udp", "127.0.0.1", "")));
+        assert_eq!(port("53:53/udp"), owned(("53/udp", "", "53")));
+    }
+
+    #[test]
+    fn invalid_ports_are_rejected() {
+        for text in ["", "http", "8080:http", "http:80", "8000-8010:80", "80/tcp:1", "80/xyz"] {
+            assert!(parse_port(text).is_err(), "{text}");
+        }
+    }
+
+    #[test]
+    fn chunked_bodies_are_joined() {
+        let body = dechunk(b"5\r\nhello\r\n7;ext=1\r\n, world\r\n0\r\n\r\n").unwrap();
+        assert_eq!(body, b"hello, world");
+
+        assert_eq!(dechunk(b"0\r\n\r\n").unwrap(), b"");
+        assert_eq!(dechunk(b"A\r\n0123456789\r\n0\r\n\r\n").unwrap(), b"0123456789");
+    }
+
+    #[test]
+    fn truncated_chunked_bodies_are_an_error() {
+        for raw in [&b"5\r\nhel"[..], b"5\r\nhello\r\n", b"zz\r\nhello\r\n0\r\n\r\n", b"5"] {
+            assert!(dechunk(raw).is_err(), "{}", String::from_utf8_lossy(raw));
+        }
+    }
+
+    #[test]
+    fn responses_are_parsed() {
+        let response = parse_response(
+            b"HTTP/1.1 201 Created\r\nContent-Type: application/json\r\n\r\n{\"Id\":\"abc\"}"
+        )
+        .unwrap();
+        assert_eq!(response.status, 201);
+        assert_eq!(response.body, b"{\"Id\":\"abc\"}");
+
+        let response = parse_response(
+            b"HTTP/1.1 200 OK\r\ntransfer-encoding:  Chunked\r\n\r\n3\r\n{}\n\r\n0\r\n\r\n"
+        )
+        .unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, b"{}\n");
+    }
+
+    #[test]
+    fn invalid_responses_are_an_error() {
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\n").is_err());
+        assert!(parse_response(b"HTTP/1.1 OK\r\n\r\n").is_err());
+        assert!(parse_response(b"").is_err());
+    }
+
+    #[test]
+    fn pass_through_variables_come_from_the_host() {
+        let dir = env::temp_dir().join(format!("ddr-spec-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("app.env"), "A=1\n# comentário\n\nHOME\nDDR_TEST_UNSET_VARIABLE\n")
+            .unwrap();
+
+        let config: ContainerConfig = serde_yaml::from_str(
+            "{env_file: [app.env], environment: [PATH, B=2, DDR_TEST_UNSET_OTHER]}"
+        )
+        .unwrap();
+        let spec = container_spec(
+            &LocalBackend,
+            &dir.to_string_lossy(),
+            &config,
+            "app:1",
+            "hash",
+            None,
+        );
+        fs::remove_dir_all(&dir).unwrap();
+
+        let expected = [
+            "A=1".to_owned(),
+            format!("HOME={}", env::var("HOME").unwrap()),
+            format!("PATH={}", env::var("PATH").unwrap()),
+            "B=2".to_owned(),
+        ];
+        assert_eq!(spec.unwrap()["Env"], json!(expected));
+    }
+}
@@ -0,0 +1,174 @@
+use serde_yaml::{from_value, Mapping};
+use shell_escape::escape;
+
+use crate::{
+    backend::Backend,
+    hosts::Inventory,
+    instances::{declared_instances, RESERVED_GROUPS},
+    models::{ContainerConfig, ServiceConfig},
+    redact::redact,
+    secrets::{remove_instance_secrets, write_instance_secrets},
+    services::{resolve_instace_command, resolve_instance_config_values, RunMode},
+    utils::unique_suffix,
+};
+
+
+/// Executa um comando dentro de uma instância declarada e devolve o código
+/// de saída do comando no container
+pub fn handle_exec(
+    inventory: &Inventory,
+    deploy_map: &Mapping,
+    instance_name: &str,
+    host: Option<&str>,
+    command: &[String],
+    interactive: bool,
+) -> anyhow::Result<i32> {
+
+    let instance = declared_instances(deploy_map, None)?
+        .into_iter()
+        .find(|instance| instance.instance_name == instance_name)
+        .ok_or_else(|| {
+            anyhow::anyhow!("Instância não declarada no arquivo de deploy: {instance_name}")
+        })?;
+
+    let hosts: Vec<String> = inventory.hosts_for(&instance)?;
+    let host: String = match host {
+        Some(host) if hosts.iter().any(|name| name == host) => host.to_owned(),
+        Some(host) => anyhow::bail!("A instância {instance_name} não roda no host {host}"),
+        None if hosts.len() == 1 => hosts[0].clone(),
+        None => anyhow::bail!(
+            "A instância {instance_name} roda em vários hosts ({}); escolha um com --host",
+            hosts.join(", ")
+        ),
+    };
+
+    let command: String = shell_words(command);
+    let session: Box<dyn Backend> = inventory.connect(&host)?;
+
+    let docker_exec: &str = if interactive { "docker exec -it" } else { "docker exec" };
+    session.attach(
+        &format!("{docker_exec} {} {command}", escape(instance_name.into())),
+        interactive
+    )
+}
+
+
+/// O que o `run` executa e onde
+pub struct RunOptions {
+    pub service: String,
+    /// Instância do serviço cuja configuração é usada, em vez da do serviço
+    pub instance: Option<String>,
+    pub host: Option<String>,
+    /// Substitui o comando do serviço; vazio mantém o configurado
+    pub command: Vec<String>,
+    pub interactive: bool,
+}
+
+
+/// Executa um contêiner avulso (`docker run --rm`) com a imagem e a
+/// configuração de um serviço declarado e devolve o código de saída dele
+pub fn handle_run(
+    inventory: &Inventory,
+    deploy_map: &Mapping,
+    options: &RunOptions,
+    dry_run: bool,
+) -> anyhow::Result<i32> {
+
+    let service_name: &str = &options.service;
+    let mut found: Vec<(String, ServiceConfig)> = Vec::new();
+    for (group_name, group) in deploy_map {
+        let Some(group_name) = group_name.as_str() else { continue };
+        if RESERVED_GROUPS.contains(&group_name) {
+            continue;
+        }
+        let Some(service) = group.as_mapping().and_then(|group| group.get(service_name)) else {
+            continue;
+        };
+        found.push((group_name.to_owned(), from_value(service.clone())?));
+    }
+
+    let (_, service) = match found.len() {
+        0 => anyhow::bail!("Serviço não declarado no arquivo de deploy: {service_name}"),
+        1 => found.remove(0),
+        _ => anyhow::bail!(
+            "O serviço {service_name} está declarado em vários grupos ({})",
+            found.iter().map(|(group, _)| group.as_str()).collect::<Vec<&str>>().join(", ")
+        ),
+    };
+
+    let container_config: ContainerConfig = match options.instance {
+        Some(ref instance_name) => {
+            let instance = service.instances.get(instance_name.as_str()).ok_or_else(|| {
+                anyhow::anyhow!("O serviço {service_name} não tem a instância {instance_name}")
+            })?;
+            from_value(instance.clone())?
+        }
+        None => ContainerConfig::default(),
+    };
+    let resolved = resolve_instance_config_values(&container_config, &service)?;
+
+    let host: String = match options.host {
+        Some(ref host) => {
+            inventory.host(host)?;
+            host.clone()
+        }
+        None => {
+            let hosts: Vec<String> = inventory.resolve_hosts(
+                service_name,
+                resolved.host.as_deref(),
+                resolved.placement.as_ref()
+            )?;
+            match hosts.as_slice() {
+                [host] => host.clone(),
+                _ => anyhow::bail!(
+                    "O serviço {service_name} roda em vários hosts ({}); escolha um com --host",
+                    hosts.join(", ")
+                ),
+            }
+        }
+    };
+
+    let image_name: String = service.image.clone().unwrap_or_else(|| service_name.to_owned());
+    let container_name: String = format!("{service_name}_ddr_run_{}", unique_suffix());
+    let command: Option<String> = (!options.command.is_empty())
+        .then(|| shell_words(&options.command));
+
+    let cmd: String = resolve_instace_command(
+        &container_name,
+        &container_config,
+        &service,
+        &image_name,
+        RunMode::OneOff { command: command.as_deref(), tty: options.interactive },
+    )?;
+
+    if dry_run {
+        println!("[{host}] {}", redact(&cmd));
+        return Ok(0);
+    }
+
+    let session: Box<dyn Backend> = inventory.connect(&host)?;
+    if let Some(ref secrets) = resolved.secrets {
+        write_instance_secrets(
+            session.as_ref(),
+            &container_name,
+            secrets,
+            resolved.secrets_owner.as_deref()
+        )?;
+    }
+
+    let from_dir: &str = &inventory.ssh_config(&host)?.from_dir;
+    let code = session.attach(&format!("cd {from_dir} && {cmd}"), options.interactive);
+
+    session.run(&remove_instance_secrets(&container_name))?;
+
+    code
+}
+
+
+/// Junta os argumentos em um comando de shell, escapando cada um
+fn shell_words(args: &[String]) -> String {
+    args.iter()
+        .map(|arg| escape(arg.into()).into_owned())
+        .collect::<Vec<String>>()
+        .join(" ")
+}
@@ -0,0 +1,308 @@
+use std::{
+    io,
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use shell_escape::escape;
+use tracing::{info, info_span, warn};
+
+use crate::{
+    hosts::Sessions,
+    models::{ContainerConfig, Hook, HookFailure, HookTarget, Hooks},
+    redact::redact,
+    secrets::{remove_instance_secrets, write_instance_secrets},
+    services::one_off_command,
+    utils::{run_remote, unique_suffix},
+};
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookStage {
+    PreDeploy,
+    PostDeploy,
+    PreStop,
+    OnFailure,
+}
+
+
+impl HookStage {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HookStage::PreDeploy => "pre_deploy",
+            HookStage::PostDeploy => "post_deploy",
+            HookStage::PreStop => "pre_stop",
+            HookStage::OnFailure => "on_failure",
+        }
+    }
+
+    fn hooks(self, hooks: &Hooks) -> &[Hook] {
+        match self {
+            HookStage::PreDeploy => &hooks.pre_deploy,
+            HookStage::PostDeploy => &hooks.post_deploy,
+            HookStage::PreStop => &hooks.pre_stop,
+            HookStage::OnFailure => &hooks.on_failure,
+        }
+    }
+}
+
+
+/// A quem os hooks pertencem e onde rodam por padrão
+pub struct HookScope<'a> {
+    pub group: &'a str,
+    pub service: Option<&'a str>,
+    pub instance: Option<&'a str>,
+    /// Host dos hooks remote e container que não definem `host`
+    pub host: Option<&'a str>,
+    /// Imagem e configuração já resolvida usadas pelos hooks container
+    pub container: Option<(&'a str, &'a ContainerConfig)>,
+}
+
+
+/// Hosts a que os hooks precisam estar conectados, em qualquer etapa
+pub fn hook_hosts(hooks: Option<&Hooks>, default_host: Option<&str>) -> Vec<String> {
+    let mut hosts: Vec<String> = Vec::new();
+    let stages = [
+        HookStage::PreDeploy,
+        HookStage::PostDeploy,
+        HookStage::PreStop,
+        HookStage::OnFailure,
+    ];
+
+    let all_hooks = hooks
+        .into_iter()
+        .flat_map(|hooks| stages.iter().flat_map(|stage| stage.hooks(hooks)));
+
+    for hook in all_hooks {
+        if hook.target == HookTarget::Local {
+            continue;
+        }
+        if let Some(host) = hook.host.as_deref().or(default_host)
+            && !hosts.iter().any(|known| known == host) {
+            hosts.push(host.to_owned());
+        }
+    }
+
+    hosts
+}
+
+
+/// Executa os hooks da etapa em ordem. Os hosts usados já devem estar
+/// conectados (`hook_hosts`). Uma falha interrompe a etapa, exceto nos hooks
+/// com `on_error: warn` e nos de `on_failure`, que apenas a registram.
+pub fn run_hooks(
+    sessions: &Sessions,
+    hooks: Option<&Hooks>,
+    stage: HookStage,
+    scope: &HookScope,
+    dry_run: bool,
+) -> anyhow::Result<()> {
+
+    let Some(hooks) = hooks else {
+        return Ok(());
+    };
+
+    for hook in stage.hooks(hooks) {
+        let name: &str = hook.name.as_deref().unwrap_or(&hook.command);
+        if dry_run {
+            info!("Executaria o hook {} {name}", stage.as_str());
+            continue;
+        }
+
+        let _entered = info_span!("hook", stage = stage.as_str(), hook = %name).entered();
+        info!("Hook {} {name}", stage.as_str());
+        let started = Instant::now();
+
+        match run_hook(sessions, hook, stage, scope) {
+            Ok(()) => {
+                info!(
+                    seconds = started.elapsed().as_secs_f64(),
+                    "Hook {} {name} ok",
+                    stage.as_str()
+                );
+            }
+            Err(err) if hook.on_error == HookFailure::Warn || stage == HookStage::OnFailure => {
+                warn!(
+                    "O hook {} {name} falhou: {}",
+                    stage.as_str(),
+                    redact(&format!("{err:#}"))
+                );
+            }
+            Err(err) => {
+                return Err(err.context(format!("O hook {} {name} falhou", stage.as_str())));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+
+fn run_hook(
+    sessions: &Sessions,
+    hook: &Hook,
+    stage: HookStage,
+    scope: &HookScope,
+) -> anyhow::Result<()> {
+
+    let host: Option<&str> = hook.host.as_deref().or(scope.host);
+
+    // Variáveis que dizem ao hook onde ele está rodando
+    let mut env: Vec<String> = vec![
+        format!("DDR_HOOK={}", stage.as_str()),
+        format!("DDR_GROUP={}", scope.group),
+    ];
+    if let Some(service) = scope.service {
+        env.push(format!("DDR_SERVICE={service}"));
+    }
+    if let Some(instance) = scope.instance {
+        env.push(format!("DDR_INSTANCE={instance}"));
+    }
+    if let Some(host) = host {
+        env.push(format!("DDR_HOST={host}"));
+    }
+
+    if hook.target == HookTarget::Local {
+        return run_local(&hook.command, &env, hook.timeout);
+    }
+
+    let host: &str = host.ok_or_else(|| {
+        anyhow::anyhow!("O hook {} precisa de host para rodar fora da máquina local", hook.command)
+    })?;
+    let from_dir: &str = &sessions.inventory().ssh_config(host)?.from_dir;
+    let session = sessions.connected(host);
+
+    // `timeout` só aceita segundos inteiros aqui
+    let timeout: String = hook.timeout
+        .map(|timeout| format!("timeout {} ", timeout.as_secs().max(1)))
+        .unwrap_or_default();
+
+    if hook.target == HookTarget::Remote {
+        let env: Vec<String> = env.iter().map(|variable| escape(variable.into()).into()).collect();
+        return run_remote(
+            session,
+            &format!(
+                "cd {from_dir} && {timeout}env {} sh -c {}",
+                env.join(" "),
+                escape(hook.command.as_str().into())
+            ),
+        );
+    }
+
+    let Some((image_name, container_config)) = scope.container else {
+        anyhow::bail!("O hook {} roda em container e precisa de um serviço", hook.command);
+    };
+
+    let base: &str = scope.instance.or(scope.service).unwrap_or(scope.group);
+    let container_name: String = format!("{base}_ddr_hook_{}", unique_suffix());
+
+    let result = container_config.secrets
+        .as_ref()
+        .map_or(Ok(()), |secrets| {
+            write_instance_secrets(
+                session,
+                &container_name,
+                secrets,
+                container_config.secrets_owner.as_deref()
+            )
+        })
+        .and_then(|()| {
+            let cmd: String = one_off_command(
+                &container_name,
+                container_config,
+                image_name,
+                &env,
+                Some(&format!("sh -c {}", escape(hook.command.as_str().into()))),
+                false,
+            );
+            run_remote(session, &format!("cd {from_dir} && {timeout}{cmd}"))
+        });
+
+    // Com timeout, o `docker run` é interrompido mas o contêiner continua.
+    // Segredos que sobram no host são um erro mesmo se o hook passou; se ele
+    // falhou, o erro dele continua sendo o principal.
+    let cleanup = run_remote(
+        session,
+        &format!(
+            "docker rm -f {container_name} >/dev/null 2>&1 || true; {}",
+            remove_instance_secrets(&container_name)
+        ),
+    );
+
+    match (result, cleanup) {
+        (result, Ok(())) => result,
+        (Ok(()), Err(cleanup_err)) => Err(cleanup_err.context(format!(
+            "Não foi possível remover o contêiner do hook {container_name} e os segredos dele"
+        ))),
+        (Err(err), Err(cleanup_err)) => Err(err.context(format!(
+            "A limpeza do contêiner {container_name} também falhou: {cleanup_err:#}"
+        ))),
+    }
+}
+
+
+/// Junta ao erro que disparou os hooks `on_failure` a falha deles, sem
+/// trocar o erro original
+pub fn keep_original_error(err: anyhow::Error, hooks: anyhow::Result<()>) -> anyhow::Error {
+    match hooks {
+        Ok(()) => err,
+        Err(hooks_err) => {
+            err.context(format!("Os hooks on_failure também falharam: {hooks_err:#}"))
+        }
+    }
+}
+
+
+/// Executa o hook na máquina local, com a saída na saída de erro do ddr
+fn run_local(command: &str, env: &[String], timeout: Option<Duration>) -> anyhow::Result<()> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .envs(env.iter().filter_map(|variable| variable.split_once('=')))
+        .stdin(Stdio::null())
+        .stdout(io::stderr())
+        .spawn()?;
+
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if timeout.is_some_and(|timeout| started.elapsed() >= timeout) {
+            child.kill().ok();
+            child.wait().ok();
+            anyhow::bail!("Comando local excedeu o tempo limite: {}", redact(command));
+        }
+        thread::sleep(Duration::from_millis(100));
+    };
+
+    if !status.success() {
+        anyhow::bail!("Comando local falhou ({status}): {}", redact(command));
+    }
+
+    Ok(())
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failure_hooks_keep_the_original_error() {
+        let err = keep_original_error(anyhow::anyhow!("deploy falhou"), Ok(()));
+        assert_eq!(format!("{err:#}"), "deploy falhou");
+
+        let err = keep_original_error(
+            anyhow::anyhow!("deploy falhou"),
+            Err(anyhow::anyhow!("notificação falhou")),
+        );
+        assert_eq!(err.root_cause().to_string(), "deploy falhou");
+        assert_eq!(
+            format!("{err:#}"),
+            "Os hooks on_failure também falharam: notificação falhou: deploy falhou"
+        );
+    }
+}
@@ -0,0 +1,357 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    env,
+};
+
+use serde_yaml::{from_value, Mapping, Value};
+use ssh2::Session;
+
+use crate::{
+    backend::{Backend, LocalBackend, SshBackend},
+    instances::DeclaredInstance,
+    models::{DockerMode, HostConfig, Placement, SSHConfig, Target},
+    utils::get_session,
+};
+
+
+/// Nome do host definido por SSH_HOST, usado quando não há `host:` nem
+/// `placement:` na instância
+pub const DEFAULT_HOST: &str = "default";
+
+/// Nome do único host do alvo local (`--target local`)
+pub const LOCAL_HOST: &str = "local";
+
+
+pub struct Host {
+    pub ssh_config: SSHConfig,
+    pub labels: HashMap<String, String>,
+}
+
+
+/// Hosts disponíveis para o deploy: o inventário `hosts:` do arquivo de
+/// deploy e o host definido pelas variáveis SSH_*
+pub struct Inventory {
+    hosts: BTreeMap<String, Host>,
+    target: Target,
+    docker: DockerMode,
+}
+
+
+impl Inventory {
+    pub fn load(
+        deploy_map: &Mapping,
+        default_ssh_config: anyhow::Result<SSHConfig>,
+    ) -> anyhow::Result<Self> {
+
+        let Some(hosts_config) = deploy_map.get("hosts").and_then(Value::as_mapping) else {
+            // Sem inventário, o único host é o definido pelas variáveis SSH_*
+            let host = Host { ssh_config: default_ssh_config?, labels: HashMap::new() };
+            return Ok(Inventory {
+                hosts: BTreeMap::from([(DEFAULT_HOST.to_owned(), host)]),
+                target: Target::Ssh,
+                docker: DockerMode::Cli,
+            });
+        };
+
+        let default_ssh_config: Option<SSHConfig> = default_ssh_config.ok();
+        let mut hosts: BTreeMap<String, Host> = BTreeMap::new();
+
+        for (name, host_config) in hosts_config {
+            let name: String = from_value(name.clone())?;
+            let host_config: HostConfig = from_value(host_config.clone())?;
+
+            let user = host_config.user
+                .or_else(|| default_ssh_config.as_ref().map(|config| config.user.clone()))
+                .ok_or_else(|| anyhow::anyhow!("Host {name} sem usuário (user ou SSH_USER)"))?;
+
+            let from_dir = host_config.dir
+                .or_else(|| default_ssh_config.as_ref().map(|config| config.from_dir.clone()))
+                .ok_or_else(|| anyhow::anyhow!("Host {name} sem diretório (dir ou DIR)"))?;
+
+            let password = host_config.password
+                .or_else(|| default_ssh_config.as_ref().map(|config| config.password.clone()))
+                .unwrap_or_default();
+
+            let mut ssh_config = SSHConfig::new(user, host_config.address, password, from_dir);
+            ssh_config.port = host_config.port.unwrap_or(22);
+            ssh_config.key_file = host_config.key_file;
+
+            hosts.insert(name, Host { ssh_config, labels: host_config.labels });
+        }
+
+        if let Some(ssh_config) = default_ssh_config
+            && !hosts.contains_key(DEFAULT_HOST) {
+            hosts.insert(DEFAULT_HOST.to_owned(), Host { ssh_config, labels: HashMap::new() });
+        }
+
+        Ok(Inventory { hosts, target: Target::Ssh, docker: DockerMode::Cli })
+    }
+
+    /// Inventário do alvo local: a máquina do ddr é o único host, no
+    /// diretório atual, e todo `host:` ou `placement:` aponta para ela
+    pub fn local() -> anyhow::Result<Self> {
+        let from_dir: String = env::current_dir()?.to_string_lossy().into_owned();
+        // `LOCAL_HOST` é só a chave do inventário; as verificações que partem
+        // da máquina local usam o endereço do host
+        let ssh_config = SSHConfig::new(
+            String::new(),
+            "localhost".to_owned(),
+            String::new(),
+            from_dir
+        );
+        let host = Host { ssh_config, labels: HashMap::new() };
+
+        Ok(Inventory {
+            hosts: BTreeMap::from([(LOCAL_HOST.to_owned(), host)]),
+            target: Target::Local,
+            docker: DockerMode::Cli,
+        })
+    }
+
+    /// Como o deploy fala com o Docker dos hosts
+    pub fn with_docker(self, docker: DockerMode) -> Self {
+        Inventory { docker, ..self }
+    }
+
+    pub fn is_local(&self) -> bool {
+        self.target == Target::Local
+    }
+
+    pub fn uses_docker_api(&self) -> bool {
+        self.docker == DockerMode::Api
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.hosts.keys().cloned().collect()
+    }
+
+    pub fn is_multi_host(&self) -> bool {
+        self.hosts.len() > 1
+    }
+
+    pub fn host(&self, name: &str) -> anyhow::Result<&Host> {
+        if self.is_local() {
+            return Ok(&self.hosts[LOCAL_HOST]);
+        }
+
+        self.hosts
+            .get(name)
+            .ok_or_else(|| anyhow::anyhow!("Host não encontrado no inventário: {name}"))
+    }
+
+    pub fn ssh_config(&self, name: &str) -> anyhow::Result<&SSHConfig> {
+        Ok(&self.host(name)?.ssh_config)
+    }
+
+    pub fn connect(&self, name: &str) -> anyhow::Result<Box<dyn Backend>> {
+        match self.target {
+            Target::Ssh => Ok(Box::new(SshBackend::new(self.ssh_session(name)?))),
+            Target::Local => Ok(Box::new(LocalBackend)),
+        }
+    }
+
+    /// Conexão SSH com o host, para o que vai além de executar comandos
+    pub fn ssh_session(&self, name: &str) -> anyhow::Result<Session> {
+        get_session(self.ssh_config(name)?)
+    }
+
+    /// Hosts onde uma instância declarada roda
+    pub fn hosts_for(&self, instance: &DeclaredInstance) -> anyhow::Result<Vec<String>> {
+        self.resolve_hosts(
+            &instance.instance_name,
+            instance.host.as_deref(),
+            instance.placement.as_ref(),
+        )
+    }
+
+    /// Resolve `host:` ou `placement:` para nomes do inventário. Sem nenhum
+    /// dos dois, usa o host padrão (ou o único host do inventário).
+    pub fn resolve_hosts(
+        &self,
+        instance_name: &str,
+        host: Option<&str>,
+        placement: Option<&Placement>,
+    ) -> anyhow::Result<Vec<String>> {
+
+        if self.is_local() {
+            return Ok(vec![LOCAL_HOST.to_owned()]);
+        }
+
+        if let Some(host) = host {
+            self.host(host)?;
+            return Ok(vec![host.to_owned()]);
+        }
+
+        if let Some(placement) = placement {
+            let hosts: Vec<String> = self.hosts
+                .iter()
+                .filter(|(name, host)| {
+                    let name_matches = placement.hosts
+                        .as_ref()
+                        .is_none_or(|names| names.contains(name));
+
+                    let labels_match = placement.labels
+                        .as_ref()
+                        .is_none_or(|labels| {
+                            labels.iter().all(|(key, value)| host.labels.get(key) == Some(value))
+                        });
+
+                    name_matches && labels_match
+                })
+                .map(|(name, _)| name.clone())
+                .collect();
+
+            if hosts.is_empty() {
+                anyhow::bail!("Nenhum host corresponde ao placement da instância {instance_name}");
+            }
+
+            return Ok(hosts);
+        }
+
+        if self.hosts.contains_key(DEFAULT_HOST) {
+            return Ok(vec![DEFAULT_HOST.to_owned()]);
+        }
+
+        match self.hosts.keys().next() {
+            Some(name) if self.hosts.len() == 1 => Ok(vec![name.clone()]),
+            _ => anyhow::bail!(
+                "A instância {instance_name} não define host nem placement e não há host padrão"
+            ),
+        }
+    }
+}
+
+
+/// Conexões SSH abertas sob demanda, uma por host
+pub struct Sessions<'a> {
+    inventory: &'a Inventory,
+    sessions: HashMap<String, Box<dyn Backend>>,
+}
+
+
+impl<'a> Sessions<'a> {
+    pub fn new(inventory: &'a Inventory) -> Self {
+        Sessions { inventory, sessions: HashMap::new() }
+    }
+
+    pub fn inventory(&self) -> &'a Inventory {
+        self.inventory
+    }
+
+    /// Garante que há uma conexão aberta com cada um dos hosts
+    pub fn connect(&mut self, names: &[String]) -> anyhow::Result<()> {
+        for name in names {
+            if !self.sessions.contains_key(name) {
+                let session = self.inventory.connect(name)?;
+                self.sessions.insert(name.clone(), session);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn get(&mut self, name: &str) -> anyhow::Result<&dyn Backend> {
+        self.connect(&[name.to_owned()])?;
+        Ok(self.sessions[name].as_ref())
+    }
+
+    /// Conexão já aberta com `connect`
+    pub fn connected(&self, name: &str) -> &dyn Backend {
+        self.sessions
+            .get(name)
+            .unwrap_or_else(|| panic!("Host {name} não conectado"))
+            .as_ref()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOSTS: &str = "
+hosts:
+  web1: {address: 10.0.0.1, user: u, dir: /d, labels: {role: web, zone: a}}
+  web2: {address: 10.0.0.2, user: u, dir: /d, labels: {role: web, zone: b}}
+  db1: {address: 10.0.0.3, user: u, dir: /d, labels: {role: db, zone: a}}
+";
+
+    fn inventory(document: &str, with_default: bool) -> Inventory {
+        let document: Mapping = serde_yaml::from_str(document).unwrap();
+        let default_ssh_config = if with_default {
+            Ok(SSHConfig::new("deploy".into(), "10.0.0.9".into(), String::new(), "/srv".into()))
+        } else {
+            Err(anyhow::anyhow!("sem SSH_HOST"))
+        };
+
+        Inventory::load(&document, default_ssh_config).unwrap()
+    }
+
+    fn placement(text: &str) -> Placement {
+        serde_yaml::from_str(text).unwrap()
+    }
+
+    #[test]
+    fn hosts_are_resolved() {
+        let inventory = inventory(HOSTS, true);
+        let cases: [(Option<&str>, Option<&str>, &[&str]); 7] = [
+            (Some("db1"), None, &["db1"]),
+            // `host:` tem precedência sobre `placement:`
+            (Some("web2"), Some("{labels: {role: db}}"), &["web2"]),
+            (None, Some("{hosts: [web2, db1]}"), &["db1", "web2"]),
+            (None, Some("{labels: {role: web}}"), &["web1", "web2"]),
+            (None, Some("{labels: {role: web, zone: a}}"), &["web1"]),
+            (None, Some("{hosts: [web1, web2], labels: {zone: b}}"), &["web2"]),
+            (None, None, &[DEFAULT_HOST]),
+        ];
+
+        for (host, placement_text, expected) in cases {
+            let placement: Option<Placement> = placement_text.map(placement);
+            let hosts = inventory.resolve_hosts("api", host, placement.as_ref()).unwrap();
+            assert_eq!(hosts, expected, "host {host:?}, placement {placement_text:?}");
+        }
+    }
+
+    #[test]
+    fn unresolvable_hosts_are_an_error() {
+        let inventory = inventory(HOSTS, false);
+        let cases: [(Option<&str>, Option<&str>, &str); 4] = [
+            (Some("web9"), None, "Host não encontrado no inventário: web9"),
+            (None, Some("{labels: {role: cache}}"), "Nenhum host corresponde ao placement"),
+            (None, Some("{hosts: [web9]}"), "Nenhum host corresponde ao placement"),
+            (None, None, "não define host nem placement e não há host padrão"),
+        ];
+
+        for (host, placement_text, message) in cases {
+            let placement: Option<Placement> = placement_text.map(placement);
+            let err = inventory.resolve_hosts("api", host, placement.as_ref()).unwrap_err();
+            assert!(err.to_string().contains(message), "{err}");
+        }
+    }
+
+    #[test]
+    fn a_single_host_is_the_default() {
+        let inventory = inventory("hosts: {only: {address: 10.0.0.1, user: u, dir: /d}}", false);
+        assert_eq!(inventory.resolve_hosts("api", None, None).unwrap(), ["only"]);
+    }
+
+    #[test]
+    fn without_an_inventory_the_ssh_host_is_used() {
+        let inventory = inventory("app: {}", true);
+        assert_eq!(inventory.names(), [DEFAULT_HOST]);
+        assert_eq!(inventory.resolve_hosts("api", None, None).unwrap(), [DEFAULT_HOST]);
+        assert_eq!(inventory.ssh_config(DEFAULT_HOST).unwrap().host, "10.0.0.9");
+    }
+
+    #[test]
+    fn the_local_target_resolves_everything_to_the_local_host() {
+        let inventory = Inventory::local().unwrap();
+        let placement = placement("{labels: {role: web}}");
+
+        assert_eq!(inventory.resolve_hosts("api", Some("web1"), None).unwrap(), [LOCAL_HOST]);
+        assert_eq!(
+            inventory.resolve_hosts("api", None, Some(&placement)).unwrap(),
+            [LOCAL_HOST]
+        );
+    }
+}
@@ -0,0 +1,160 @@
+use serde_yaml::{from_value, Mapping, Value};
+
+use crate::{
+    models::{ContainerConfig, InstanceSelector, Placement, ServiceConfig},
+    utils::matches_any,
+};
+
+
+/// Grupos de primeiro nível que não contêm serviços
+pub const RESERVED_GROUPS: [&str; 4] = ["define", "hosts", "networks", "volumes"];
+
+/// Chaves de um grupo de serviços que configuram o grupo e não são serviços
+pub const GROUP_SETTINGS: [&str; 2] = ["smoke_tests", "hooks"];
+
+
+/// Uma instância (container) declarada no arquivo de deploy
+#[derive(Debug, Clone)]
+pub struct DeclaredInstance {
+    pub group_name: String,
+    pub service_name: String,
+    pub image_name: String,
+    pub instance_name: String,
+    pub host: Option<String>,
+    pub placement: Option<Placement>,
+}
+
+
+/// Lista as instâncias declaradas em todos os grupos de serviços, ou apenas
+/// no grupo informado
+pub fn declared_instances(
+    deploy_map: &Mapping,
+    group_name: Option<&str>,
+) -> anyhow::Result<Vec<DeclaredInstance>> {
+
+    if let Some(group_name) = group_name
+        && deploy_map.get(group_name).is_none() {
+        anyhow::bail!("Grupo não encontrado: {group_name}");
+    }
+
+    let mut instances: Vec<DeclaredInstance> = Vec::new();
+    for (group, services) in deploy_map.iter() {
+        let Some(group) = group.as_str() else { continue };
+        if RESERVED_GROUPS.contains(&group) || group_name.is_some_and(|name| name != group) {
+            continue;
+        }
+
+        let Some(services) = services.as_mapping() else { continue };
+        for (service_name, service_config) in group_services(services).iter() {
+            let service_name: String = from_value(service_name.clone())?;
+            let service: ServiceConfig = from_value(service_config.clone())?;
+            let image_name: String = service.image
+                .clone()
+                .unwrap_or_else(|| service_name.clone());
+
+            for (instance_name, instance_value) in service.instances.iter() {
+                let Some(instance_name) = instance_name.as_str() else { continue };
+
+                // `host`/`placement` da instância substituem os do serviço
+                let instance_host = instance_value.get("host").and_then(Value::as_str);
+                let instance_placement: Option<Placement> = instance_value
+                    .get("placement")
+                    .map(|placement| from_value(placement.clone()))
+                    .transpose()?;
+
+                let (host, placement) = if instance_host.is_some() || instance_placement.is_some() {
+                    (instance_host.map(str::to_owned), instance_placement)
+                } else {
+                    (service.host.clone(), service.placement.clone())
+                };
+
+                instances.push(DeclaredInstance {
+                    group_name: group.to_owned(),
+                    service_name: service_name.clone(),
+                    image_name: image_name.clone(),
+                    instance_name: instance_name.to_owned(),
+                    host,
+                    placement,
+                });
+            }
+        }
+    }
+
+    Ok(instances)
+}
+
+
+/// Serviços de um grupo, sem as chaves de configuração do grupo
+pub fn group_services(group: &Mapping) -> Mapping {
+    group
+        .iter()
+        .filter(|(key, _)| key.as_str().is_none_or(|key| !GROUP_SETTINGS.contains(&key)))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect()
+}
+
+
+/// Confere se os serviços e instâncias dos grupos têm os tipos esperados,
+/// para que um erro aponte o lugar no arquivo (`grupo.serviço.campo`)
+pub fn validate_services(document: &Value) -> anyhow::Result<()> {
+    let Some(groups) = document.as_mapping() else { return Ok(()) };
+
+    for (group_name, group) in groups {
+        let Some(group_name) = group_name.as_str() else { continue };
+        let Some(group) = group.as_mapping() else { continue };
+        if RESERVED_GROUPS.contains(&group_name) {
+            continue;
+        }
+
+        for (service_name, service) in group_services(group) {
+            let service_name: &str = service_name.as_str().unwrap_or_default();
+            let config: ServiceConfig = from_value(service).map_err(|err| {
+                anyhow::anyhow!("Configuração inválida em {group_name}.{service_name}: {err}")
+            })?;
+
+            for (instance_name, instance) in config.instances {
+                if instance.is_null() {
+                    continue;
+                }
+                let instance_name: &str = instance_name.as_str().unwrap_or_default();
+                from_value::<ContainerConfig>(instance).map_err(|err| {
+                    anyhow::anyhow!(
+                        "Configuração inválida em \
+                         {group_name}.{service_name}.instances.{instance_name}: {err}"
+                    )
+                })?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+
+/// Seleciona as instâncias declaradas que casam com o seletor. Um seletor
+/// vazio seleciona todas as instâncias.
+pub fn select_instances(
+    deploy_map: &Mapping,
+    selector: &InstanceSelector,
+) -> anyhow::Result<Vec<DeclaredInstance>> {
+
+    let mut selected: Vec<DeclaredInstance> = Vec::new();
+    for instance in declared_instances(deploy_map, selector.group_name.as_deref())? {
+        let service_matches = selector.services.is_empty()
+            || matches_any(&selector.services, &instance.service_name)?
+            || matches_any(&selector.services, &instance.image_name)?;
+
+        let instance_matches = selector.instances.is_empty()
+            || matches_any(&selector.instances, &instance.instance_name)?;
+
+        if service_matches && instance_matches {
+            selected.push(instance);
+        }
+    }
+
+    if selected.is_empty() {
+        anyhow::bail!("Nenhuma instância declarada corresponde à seleção");
+    }
+
+    Ok(selected)
+}
@@ -0,0 +1,476 @@
+use std::{collections::HashMap, env};
+
+use serde_yaml::{Mapping, Number, Value};
+
+use crate::replicas::{INDEX_PLACEHOLDER, NUMERIC_FIELDS};
+
+
+/// Campos booleanos da configuração; como nos de `NUMERIC_FIELDS`, um
+/// texto com `${...}` que vira `true` ou `false` passa a ser um booleano
+const BOOLEAN_FIELDS: [&str; 2] = ["https", "insecure"];
+
+
+/// Lê os valores escalares do bloco `define:`
+pub fn parse_variables(document: &Value) -> HashMap<String, Value> {
+    let mut variables = HashMap::new();
+
+    // Se não tiver `define`, retorna HashMap vazio
+    let Some(define_values) = document.get("define").and_then(Value::as_mapping) else {
+        return variables;
+    };
+
+    for (key, value) in define_values {
+        let is_scalar = matches!(value, Value::String(_) | Value::Number(_) | Value::Bool(_));
+        if let (Some(k), true) = (key.as_str(), is_scalar) {
+            variables.insert(k.to_string(), value.clone());
+        }
+    }
+
+    variables
+}
+
+
+/// Substitui as expressões `${...}` das chaves e valores do documento já
+/// lido, no estilo do docker compose:
+///
+/// - `${VAR}`: valor de `VAR`
+/// - `${VAR:-padrão}` / `${VAR-padrão}`: padrão se `VAR` estiver vazia ou
+///   não definida / apenas se não definida
+/// - `${VAR:?mensagem}` / `${VAR?mensagem}`: erro se `VAR` estiver vazia ou
+///   não definida / apenas se não definida
+/// - `$$`: um `$` literal
+/// - `${index}`: mantido como está, para os templates de réplicas
+///
+/// As variáveis vêm do bloco `define:` e, na falta dele, do ambiente (que já
+/// inclui o arquivo de variáveis). Definições podem referenciar outras. Os
+/// valores interpolados são textos, exceto nos campos de `NUMERIC_FIELDS` e
+/// `BOOLEAN_FIELDS`, onde um resultado inteiro ou booleano volta a ter o
+/// tipo (`port: ${PORT:-8080}` é um número).
+pub fn interpolate(document: Value, defines: &HashMap<String, Value>) -> anyhow::Result<Value> {
+    interpolate_with(document, defines, &|name| env::var(name).ok())
+}
+
+
+/// `interpolate` com as variáveis de ambiente lidas por `environment`
+fn interpolate_with(
+    document: Value,
+    defines: &HashMap<String, Value>,
+    environment: &dyn Fn(&str) -> Option<String>,
+) -> anyhow::Result<Value> {
+    let mut interpolator = Interpolator {
+        defines,
+        environment,
+        resolved: HashMap::new(),
+        resolving: Vec::new(),
+    };
+
+    interpolator.interpolate_value(document, &mut Vec::new(), false)
+}
+
+
+struct Interpolator<'a> {
+    defines: &'a HashMap<String, Value>,
+    /// Variáveis de ambiente, usadas na falta de uma definição
+    environment: &'a dyn Fn(&str) -> Option<String>,
+    /// Definições já interpoladas
+    resolved: HashMap<String, String>,
+    /// Definições sendo interpoladas, para detectar ciclos
+    resolving: Vec<String>,
+}
+
+
+impl Interpolator<'_> {
+    /// Interpola os escalares de `value`; `path` é a posição no documento,
+    /// usada nas mensagens de erro. `field` diz se `value` é o valor do
+    /// campo no fim de `path`, que pode ter o tipo restaurado.
+    fn interpolate_value(
+        &mut self,
+        value: Value,
+        path: &mut Vec<String>,
+        field: bool,
+    ) -> anyhow::Result<Value> {
+        match value {
+            Value::String(text) => {
+                let interpolated: String = self.interpolate(&text).map_err(|err| {
+                    anyhow::anyhow!("Erro de interpolação em {}: {err}", display_path(path))
+                })?;
+
+                if field
+                    && text.contains("${")
+                    && let Some(name) = path.last()
+                    && let Some(value) = typed_scalar(name, &interpolated) {
+                    return Ok(value);
+                }
+
+                Ok(Value::String(interpolated))
+            }
+            Value::Sequence(items) => {
+                let mut interpolated = Vec::with_capacity(items.len());
+                for (index, item) in items.into_iter().enumerate() {
+                    path.push(index.to_string());
+                    interpolated.push(self.interpolate_value(item, path, false)?);
+                    path.pop();
+                }
+                Ok(Value::Sequence(interpolated))
+            }
+            Value::Mapping(map) => {
+                let mut interpolated = Mapping::with_capacity(map.len());
+                for (key, value) in map {
+                    let key: Value = self.interpolate_value(key, path, false)?;
+                    let name: String = match &key {
+                        Value::String(name) => name.clone(),
+                        other => serde_yaml::to_string(other)?.trim_end().to_owned(),
+                    };
+
+                    path.push(name);
+                    let value: Value = self.interpolate_value(value, path, true)?;
+                    if interpolated.insert(key, value).is_some() {
+                        anyhow::bail!("Chave duplicada após interpolação: {}", display_path(path));
+                    }
+                    path.pop();
+                }
+                Ok(Value::Mapping(interpolated))
+            }
+            Value::Tagged(mut tagged) => {
+                tagged.value = self.interpolate_value(tagged.value, path, field)?;
+                Ok(Value::Tagged(tagged))
+            }
+            other => Ok(other),
+        }
+    }
+
+    fn interpolate(&mut self, text: &str) -> Result<String, String> {
+        let mut output = String::with_capacity(text.len());
+        let mut rest: &str = text;
+
+        while let Some(index) = rest.find('$') {
+            output.push_str(&rest[..index]);
+            let after: &str = &rest[index + 1..];
+
+            if let Some(after) = after.strip_prefix('$') {
+                output.push('$');
+                rest = after;
+            } else if let Some(expression) = after.strip_prefix('{') {
+                let end = find_closing_brace(expression)
+                    .ok_or_else(|| "expressão `${` sem `}`".to_owned())?;
+
+                output.push_str(&self.evaluate(&expression[..end])?);
+                rest = &expression[end + 1..];
+            } else {
+                output.push('$');
+                rest = after;
+            }
+        }
+
+        output.push_str(rest);
+        Ok(output)
+    }
+
+    /// Avalia o conteúdo de uma expressão `${...}`
+    fn evaluate(&mut self, expression: &str) -> Result<String, String> {
+        let name_length: usize = expression
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+            .unwrap_or(expression.len());
+        let (name, operator) = expression.split_at(name_length);
+
+        if name.is_empty() {
+            return Err(format!("nome de variável inválido em `${{{expression}}}`"));
+        }
+
+        // O índice das réplicas só é conhecido quando os templates são expandidos
+        if expression == "index" {
+            return Ok(INDEX_PLACEHOLDER.to_owned());
+        }
+
+        let value: Option<String> = self.lookup(name)?;
+        if operator.is_empty() {
+            return value.ok_or_else(|| format!("variável não encontrada: {name}"));
+        }
+
+        // (vazia conta como não definida, é obrigatória, argumento)
+        let (empty_is_unset, required, argument) = if let Some(argument) =
+            operator.strip_prefix(":-") {
+            (true, false, argument)
+        } else if let Some(argument) = operator.strip_prefix(":?") {
+            (true, true, argument)
+        } else if let Some(argument) = operator.strip_prefix('-') {
+            (false, false, argument)
+        } else if let Some(argument) = operator.strip_prefix('?') {
+            (false, true, argument)
+        } else {
+            return Err(format!("operador inválido em `${{{expression}}}`"));
+        };
+
+        let unset: bool = match &value {
+            None => true,
+            Some(value) => empty_is_unset && value.is_empty(),
+        };
+        if !unset {
+            return Ok(value.unwrap_or_default());
+        }
+
+        // O argumento só é interpolado quando for usado
+        let argument: String = self.interpolate(argument)?;
+
+        if required {
+            return Err(if argument.is_empty() {
+                format!("variável obrigatória não definida: {name}")
+            } else {
+                format!("{name}: {argument}")
+            });
+        }
+
+        Ok(argument)
+    }
+
+    /// Valor de uma variável: definição (interpolada) ou variável de ambiente
+    fn lookup(&mut self, name: &str) -> Result<Option<String>, String> {
+        if let Some(value) = self.resolved.get(name) {
+            return Ok(Some(value.clone()));
+        }
+
+        let raw: String = match self.defines.get(name) {
+            None => return Ok((self.environment)(name)),
+            Some(Value::String(value)) => value.clone(),
+            Some(Value::Number(value)) => value.to_string(),
+            Some(Value::Bool(value)) => value.to_string(),
+            Some(_) => return Ok(None),
+        };
+
+        if self.resolving.iter().any(|resolving| resolving == name) {
+            let mut cycle: Vec<&str> = self.resolving.iter().map(String::as_str).collect();
+            cycle.push(name);
+            return Err(format!("definições cíclicas: {}", cycle.join(" -> ")));
+        }
+
+        self.resolving.push(name.to_owned());
+        let value = self.interpolate(&raw);
+        self.resolving.pop();
+
+        let value: String = value.map_err(|err| {
+            if err.starts_with("definições cíclicas") {
+                err
+            } else {
+                format!("na definição {name}: {err}")
+            }
+        })?;
+
+        self.resolved.insert(name.to_owned(), value.clone());
+        Ok(Some(value))
+    }
+}
+
+
+/// Posição do `}` que fecha a expressão, considerando expressões aninhadas
+fn find_closing_brace(expression: &str) -> Option<usize> {
+    let bytes: &[u8] = expression.as_bytes();
+    let mut depth: usize = 0;
+    let mut index: usize = 0;
+
+    while index < bytes.len() {
+        match bytes[index] {
+            b'$' if bytes.get(index + 1) == Some(&b'$') => index += 1,
+            b'$' if bytes.get(index + 1) == Some(&b'{') => {
+                depth += 1;
+                index += 1;
+            }
+            b'}' if depth == 0 => return Some(index),
+            b'}' => depth -= 1,
+            _ => {}
+        }
+        index += 1;
+    }
+
+    None
+}
+
+
+/// Número inteiro ou booleano que o resultado da interpolação volta a ser
+/// no campo `field`; `None` nos demais campos ou se o texto não for um deles
+fn typed_scalar(field: &str, text: &str) -> Option<Value> {
+    if NUMERIC_FIELDS.contains(&field) {
+        return text.parse::<u64>().ok().map(|number| Value::Number(Number::from(number)));
+    }
+    if BOOLEAN_FIELDS.contains(&field) {
+        return text.parse::<bool>().ok().map(Value::Bool);
+    }
+
+    None
+}
+
+
+/// Caminho no documento no formato `grupo.serviço.campo`
+fn display_path(path: &[String]) -> String {
+    if path.is_empty() {
+        "<raiz>".to_owned()
+    } else {
+        path.join(".")
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interpolated(document: &str) -> anyhow::Result<Value> {
+        interpolated_with_env(document, &[])
+    }
+
+    /// Interpola com `environment` no lugar das variáveis de ambiente
+    fn interpolated_with_env(
+        document: &str,
+        environment: &[(&str, &str)],
+    ) -> anyhow::Result<Value> {
+        let document: Value = serde_yaml::from_str(document).unwrap();
+        let defines: HashMap<String, Value> = parse_variables(&document);
+        let environment = |name: &str| {
+            environment
+                .iter()
+                .find(|(variable, _)| *variable == name)
+                .map(|(_, value)| (*value).to_owned())
+        };
+        interpolate_with(document, &defines, &environment)
+    }
+
+    fn field(document: &Value, path: &str) -> Value {
+        path.split('.').fold(document.clone(), |value, key| value[key].clone())
+    }
+
+    #[test]
+    fn numeric_fields_become_numbers() {
+        let document = interpolated(
+            "define: {api_port: '8001', retries: 3}\n\
+             api: {port: '${api_port}', remotecheck: {port: '80${retries}', retries: '${retries}'}}"
+        )
+        .unwrap();
+        assert_eq!(field(&document, "api.port"), Value::from(8001));
+        assert_eq!(field(&document, "api.remotecheck.port"), Value::from(803));
+        assert_eq!(field(&document, "api.remotecheck.retries"), Value::from(3));
+    }
+
+    #[test]
+    fn boolean_fields_become_booleans() {
+        let document = interpolated(
+            "define: {tls: true}\napi: {remotecheck: {https: '${tls}', insecure: '${tls}'}}"
+        )
+        .unwrap();
+        assert_eq!(field(&document, "api.remotecheck.https"), Value::from(true));
+        assert_eq!(field(&document, "api.remotecheck.insecure"), Value::from(true));
+    }
+
+    #[test]
+    fn other_fields_stay_strings() {
+        let document = interpolated_with_env(
+            "define: {tag: '1.10', version: 1.0, debug: true}\n\
+             api: {image: '${IMG}', tag: '${tag}', version: '${version}', \
+             command: '${CMD}', debug: '${debug}', password: '${PASSWORD}'}",
+            &[("IMG", "1.0"), ("CMD", "true"), ("PASSWORD", "1234")],
+        )
+        .unwrap();
+        assert_eq!(field(&document, "api.image"), Value::from("1.0"));
+        assert_eq!(field(&document, "api.tag"), Value::from("1.10"));
+        assert_eq!(field(&document, "api.version"), Value::from("1.0"));
+        assert_eq!(field(&document, "api.command"), Value::from("true"));
+        assert_eq!(field(&document, "api.debug"), Value::from("true"));
+        assert_eq!(field(&document, "api.password"), Value::from("1234"));
+    }
+
+    #[test]
+    fn numeric_fields_without_an_expression_are_kept() {
+        let document = interpolated("api: {port: '8001', status: 200}").unwrap();
+        assert_eq!(field(&document, "api.port"), Value::from("8001"));
+        assert_eq!(field(&document, "api.status"), Value::from(200));
+    }
+
+    #[test]
+    fn text_around_an_expression_keeps_a_string() {
+        let document = interpolated(
+            "define: {port: 8001, tag: '1.0'}\n\
+             api: {url: 'http://api:${port}', image: 'app:${tag}'}"
+        )
+        .unwrap();
+        assert_eq!(field(&document, "api.url"), Value::from("http://api:8001"));
+        assert_eq!(field(&document, "api.image"), Value::from("app:1.0"));
+    }
+
+    #[test]
+    fn list_items_stay_strings() {
+        let document = interpolated("define: {port: '8001'}\napi: {port: ['${port}']}").unwrap();
+        assert_eq!(field(&document, "api.port")[0], Value::from("8001"));
+    }
+
+    #[test]
+    fn non_scalar_results_stay_strings() {
+        let document = interpolated("define: {tag: 'app:1'}\napi: {image: '${tag}'}").unwrap();
+        assert_eq!(field(&document, "api.image"), Value::from("app:1"));
+    }
+
+    #[test]
+    fn environment_variables_and_defaults_are_used() {
+        let document = interpolated_with_env(
+            "define: {host: defined}\n\
+             api: {host: '${DB_HOST}', port: '${PORT:-8002}', name: '${host}'}",
+            &[("DB_HOST", "db.local"), ("host", "from-env")],
+        )
+        .unwrap();
+        assert_eq!(field(&document, "api.host"), Value::from("db.local"));
+        assert_eq!(field(&document, "api.port"), Value::from(8002));
+        // As definições têm precedência sobre o ambiente
+        assert_eq!(field(&document, "api.name"), Value::from("defined"));
+    }
+
+    #[test]
+    fn colon_dash_also_replaces_empty_values() {
+        let document = interpolated(
+            "define: {empty: ''}\napi: {a: '${empty:-x}', b: '${empty-x}', c: '${missing-y}'}"
+        )
+        .unwrap();
+        assert_eq!(field(&document, "api.a"), Value::from("x"));
+        assert_eq!(field(&document, "api.b"), Value::from(""));
+        assert_eq!(field(&document, "api.c"), Value::from("y"));
+    }
+
+    #[test]
+    fn required_variables_report_the_location() {
+        let err = interpolated("api: {image: '${IMAGE:?defina a imagem}'}")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("api.image"), "{err}");
+        assert!(err.contains("defina a imagem"), "{err}");
+
+        let err = interpolated("define: {empty: ''}\napi: {image: '${empty:?}'}")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("obrigatória"), "{err}");
+
+        // Sem `:`, uma variável vazia é aceita
+        let document = interpolated("define: {empty: ''}\napi: {image: '${empty?}'}").unwrap();
+        assert_eq!(field(&document, "api.image"), Value::from(""));
+    }
+
+    #[test]
+    fn defines_can_reference_other_defines() {
+        let document = interpolated(
+            "define: {host: db, url: 'pg://${host}:5432'}\napi: {db: '${url}'}"
+        )
+        .unwrap();
+        assert_eq!(field(&document, "api.db"), Value::from("pg://db:5432"));
+    }
+
+    #[test]
+    fn cyclic_defines_are_an_error() {
+        let err = interpolated("define: {a: '${b}', b: '${a}'}\napi: {x: '${a}'}")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("cíclicas"), "{err}");
+        assert!(err.contains("a -> b -> a") || err.contains("b -> a -> b"), "{err}");
+    }
+
+    #[test]
+    fn dollar_escapes_and_index_are_kept() {
+        let document = interpolated("api: {cmd: 'echo $$HOME', name: 'api${index}'}").unwrap();
+        assert_eq!(field(&document, "api.cmd"), Value::from("echo $HOME"));
+        assert_eq!(field(&document, "api.name"), Value::from("api${index}"));
+    }
+}
@@ -0,0 +1,293 @@
+use std::collections::HashSet;
+
+use serde_yaml::{from_value, Mapping, Value};
+use tracing::info;
+
+use crate::{
+    deploy::resolve_group_order,
+    hooks::{hook_hosts, run_hooks, HookScope, HookStage},
+    hosts::{Inventory, Sessions},
+    instances::{group_services, select_instances, DeclaredInstance, RESERVED_GROUPS},
+    models::{ContainerConfig, Hooks, InstanceSelector, ServiceConfig},
+    redact::redact,
+    secrets::remove_instance_secrets,
+    services::{resolve_instance_config_values, service_order},
+    utils::{confirm, previous_container, run_remote},
+};
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LifecycleAction {
+    Stop,
+    Start,
+    Restart,
+    Down,
+}
+
+
+/// Opções do `down` para remover também os recursos declarados
+#[derive(Default)]
+pub struct DownOptions {
+    pub volumes: bool,
+    pub networks: bool,
+    pub yes: bool,
+}
+
+
+pub fn handle_lifecycle(
+    inventory: &Inventory,
+    deploy_map: &Mapping,
+    selector: &InstanceSelector,
+    action: LifecycleAction,
+    down_options: &DownOptions,
+    dry_run: bool,
+) -> anyhow::Result<()> {
+
+    let selected: Vec<DeclaredInstance> = select_instances(deploy_map, selector)?;
+    let mut instances: Vec<DeclaredInstance> = dependency_order(deploy_map, selected)?;
+
+    // Parar e remover acontece na ordem inversa do deploy
+    if matches!(action, LifecycleAction::Stop | LifecycleAction::Down) {
+        instances.reverse();
+    }
+
+    let volumes: Vec<String> = if action == LifecycleAction::Down && down_options.volumes {
+        declared_volumes(deploy_map)
+    } else {
+        Vec::new()
+    };
+
+    let networks: Vec<String> = if action == LifecycleAction::Down && down_options.networks {
+        declared_networks(deploy_map)
+    } else {
+        Vec::new()
+    };
+
+    if !down_options.yes && !dry_run {
+        if !volumes.is_empty()
+            && !confirm(&format!("Remover os volumes {} e seus dados?", volumes.join(", ")))? {
+            anyhow::bail!("Operação cancelada");
+        }
+        if !networks.is_empty()
+            && !confirm(&format!("Remover as redes {}?", networks.join(", ")))? {
+            anyhow::bail!("Operação cancelada");
+        }
+    }
+
+    // Comandos na ordem em que devem rodar, cada um com o seu host e a
+    // instância que param, se for o caso
+    let mut commands: Vec<(String, String, Option<&DeclaredInstance>)> = Vec::new();
+    for instance in &instances {
+        let cmd = match action {
+            LifecycleAction::Stop => format!("docker stop {}", instance.instance_name),
+            LifecycleAction::Start => format!("docker start {}", instance.instance_name),
+            LifecycleAction::Restart => format!("docker restart {}", instance.instance_name),
+            LifecycleAction::Down => format!(
+                "docker rm -f {name} {} || true; {}",
+                previous_container(&instance.instance_name),
+                remove_instance_secrets(&instance.instance_name),
+                name = instance.instance_name
+            ),
+        };
+
+        let stops: bool = action != LifecycleAction::Start;
+        for host in inventory.hosts_for(instance)? {
+            commands.push((host, cmd.clone(), stops.then_some(instance)));
+        }
+    }
+
+    // Um host pode nunca ter tido o volume ou a rede; só erros de quem existe
+    // (ex: ainda em uso) interrompem o down
+    for host in inventory.names() {
+        for volume in &volumes {
+            commands.push((host.clone(), remove_if_exists("volume", volume), None));
+        }
+        for network in networks.iter().rev() {
+            commands.push((host.clone(), remove_if_exists("network", network), None));
+        }
+    }
+
+    let mut sessions: Sessions = Sessions::new(inventory);
+    let mut hooks_done: HashSet<String> = HashSet::new();
+
+    for (host, cmd, stopped) in commands {
+        if let Some(instance) = stopped {
+            run_pre_stop(&mut sessions, deploy_map, instance, &host, &mut hooks_done, dry_run)?;
+        }
+
+        if dry_run {
+            println!("[{host}] {}", redact(&cmd));
+            continue;
+        }
+
+        info!(host = %host, "{}", redact(&cmd));
+        run_remote(sessions.get(&host)?, &cmd)?;
+    }
+
+    Ok(())
+}
+
+
+/// `docker volume rm` ou `docker network rm` só se o objeto existir no host
+fn remove_if_exists(kind: &str, name: &str) -> String {
+    format!("if docker {kind} inspect {name} >/dev/null 2>&1; then docker {kind} rm {name}; fi")
+}
+
+
+/// Executa os hooks `pre_stop` antes de parar a instância no host: os do
+/// grupo e do serviço na primeira instância de cada um e os da instância
+fn run_pre_stop(
+    sessions: &mut Sessions,
+    deploy_map: &Mapping,
+    instance: &DeclaredInstance,
+    host: &str,
+    hooks_done: &mut HashSet<String>,
+    dry_run: bool,
+) -> anyhow::Result<()> {
+
+    let group: &Mapping = deploy_map
+        .get(instance.group_name.as_str())
+        .and_then(Value::as_mapping)
+        .expect("Grupo de instância declarada não encontrado");
+    let group_hooks: Option<Hooks> = group
+        .get("hooks")
+        .map(|hooks| from_value(hooks.clone()))
+        .transpose()?;
+
+    let service: ServiceConfig = from_value(
+        group
+            .get(instance.service_name.as_str())
+            .cloned()
+            .expect("Serviço de instância declarada não encontrado")
+    )?;
+    let container_config: ContainerConfig = from_value(
+        service.instances
+            .get(instance.instance_name.as_str())
+            .cloned()
+            .expect("Instância declarada não encontrada")
+    )?;
+
+    let service_defaults = resolve_instance_config_values(&ContainerConfig::default(), &service)?;
+    let resolved = resolve_instance_config_values(&container_config, &service)?;
+
+    // Os hooks do grupo rodam no host padrão, se não indicarem outro
+    let default_host: Option<String> = sessions.inventory()
+        .resolve_hosts(&instance.group_name, None, None)
+        .ok()
+        .and_then(|hosts| hosts.into_iter().next());
+
+    let group_scope = HookScope {
+        group: &instance.group_name,
+        service: None,
+        instance: None,
+        host: default_host.as_deref(),
+        container: None,
+    };
+    let service_scope = HookScope {
+        service: Some(&instance.service_name),
+        host: Some(host),
+        container: Some((&instance.image_name, &service_defaults)),
+        ..group_scope
+    };
+    let instance_scope = HookScope {
+        instance: Some(&instance.instance_name),
+        container: Some((&instance.image_name, &resolved)),
+        ..service_scope
+    };
+
+    if !dry_run {
+        let mut hosts: Vec<String> = hook_hosts(group_hooks.as_ref(), group_scope.host);
+        hosts.extend(hook_hosts(service.hooks.as_ref(), Some(host)));
+        hosts.extend(hook_hosts(container_config.hooks.as_ref(), Some(host)));
+        sessions.connect(&hosts)?;
+    }
+
+    if hooks_done.insert(instance.group_name.clone()) {
+        run_hooks(sessions, group_hooks.as_ref(), HookStage::PreStop, &group_scope, dry_run)?;
+    }
+    if hooks_done.insert(format!("{}/{}", instance.group_name, instance.service_name)) {
+        run_hooks(sessions, service.hooks.as_ref(), HookStage::PreStop, &service_scope, dry_run)?;
+    }
+
+    run_hooks(
+        sessions,
+        container_config.hooks.as_ref(),
+        HookStage::PreStop,
+        &instance_scope,
+        dry_run
+    )
+}
+
+
+/// Ordena as instâncias na ordem de deploy: grupos pelas dependências entre
+/// eles e, dentro de cada grupo, serviços pelas ondas de `depends_on`
+fn dependency_order(
+    deploy_map: &Mapping,
+    mut instances: Vec<DeclaredInstance>,
+) -> anyhow::Result<Vec<DeclaredInstance>> {
+
+    let group_names: Vec<String> = deploy_map
+        .keys()
+        .filter_map(Value::as_str)
+        .filter(|name| !RESERVED_GROUPS.contains(name))
+        .map(str::to_owned)
+        .collect();
+
+    let mut order: Vec<(String, String)> = Vec::new();
+    for group_name in resolve_group_order(deploy_map, &group_names)? {
+        let Some(services) = deploy_map.get(group_name.as_str()).and_then(Value::as_mapping) else {
+            continue;
+        };
+
+        for service_name in service_order(&group_services(services))? {
+            order.push((group_name.clone(), service_name));
+        }
+    }
+
+    instances.sort_by_key(|instance| {
+        order
+            .iter()
+            .position(|(group, service)| {
+                *group == instance.group_name && *service == instance.service_name
+            })
+            .unwrap_or(usize::MAX)
+    });
+
+    Ok(instances)
+}
+
+
+fn declared_volumes(deploy_map: &Mapping) -> Vec<String> {
+    deploy_map
+        .get("volumes")
+        .and_then(Value::as_mapping)
+        .map(|volumes| {
+            volumes
+                .keys()
+                .filter_map(Value::as_str)
+                .map(str::to_owned)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+
+/// Nomes das redes declaradas, usando `name` quando informado
+fn declared_networks(deploy_map: &Mapping) -> Vec<String> {
+    deploy_map
+        .get("networks")
+        .and_then(Value::as_mapping)
+        .map(|networks| {
+            networks
+                .iter()
+                .filter_map(|(key, value)| {
+                    value
+                        .get("name")
+                        .and_then(Value::as_str)
+                        .or_else(|| key.as_str())
+                        .map(str::to_owned)
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
@@ -0,0 +1,30 @@
+use tracing::Level;
+use tracing_subscriber::fmt::format::FmtSpan;
+
+use crate::models::LogFormat;
+
+
+/// Configura os logs, sempre na saída de erro para não se misturar com a
+/// saída dos comandos. Sem opções mostra o andamento; `-v` inclui os
+/// comandos remotos, `-vv` a saída deles e `-q` só avisos e erros. Ao
+/// fechar, cada etapa (grupo, onda, serviço, host e instância) registra
+/// quanto tempo levou.
+pub fn init_logging(verbose: u8, quiet: bool, format: LogFormat) {
+    let level: Level = match (quiet, verbose) {
+        (true, _) => Level::WARN,
+        (false, 0) => Level::INFO,
+        (false, 1) => Level::DEBUG,
+        (false, _) => Level::TRACE,
+    };
+
+    let builder = tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(std::io::stderr)
+        .with_target(false)
+        .with_span_events(FmtSpan::CLOSE);
+
+    match format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Json => builder.json().with_current_span(true).with_span_list(true).init(),
+    }
+}
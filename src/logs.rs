@@ -0,0 +1,479 @@
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, ErrorKind, IsTerminal, Read},
+    process::{Child, Command, Stdio},
+    sync::mpsc,
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use regex::Regex;
+use serde_yaml::Mapping;
+use shell_escape::escape;
+use ssh2::{Channel, Session};
+use tracing::warn;
+
+use crate::{
+    hosts::Inventory,
+    instances::{select_instances, DeclaredInstance},
+    models::InstanceSelector,
+};
+
+
+const COLORS: [&str; 6] = ["\x1b[36m", "\x1b[33m", "\x1b[32m", "\x1b[35m", "\x1b[34m", "\x1b[31m"];
+const RESET: &str = "\x1b[0m";
+const MAX_RECONNECT_ATTEMPTS: u32 = 10;
+
+
+/// Opções repassadas ao `docker logs`
+pub struct LogsOptions {
+    pub follow: bool,
+    pub since: Option<String>,
+    pub tail: Option<String>,
+    pub grep: Option<Regex>,
+    pub invert_match: bool,
+}
+
+
+struct LogStream {
+    host: String,
+    instance_name: String,
+    prefix: String,
+    channel: Channel,
+    stdout_buffer: Vec<u8>,
+    stderr_buffer: Vec<u8>,
+    /// Timestamp do Docker da última linha lida, para retomar dali após uma
+    /// queda. Só existe no modo follow, em que as linhas têm timestamps.
+    last_timestamp: Option<String>,
+    /// Timestamp a partir do qual o stream foi aberto; as linhas com ele
+    /// exato já foram impressas antes da queda
+    resumed_from: Option<String>,
+    done: bool,
+    failed: bool,
+}
+
+
+pub fn handle_logs(
+    inventory: &Inventory,
+    deploy_map: &Mapping,
+    selector: &InstanceSelector,
+    options: &LogsOptions,
+) -> anyhow::Result<()> {
+
+    let instances: Vec<DeclaredInstance> = select_instances(deploy_map, selector)?;
+    let mut targets: Vec<(String, String)> = Vec::new();
+    for instance in instances {
+        for host in inventory.hosts_for(&instance)? {
+            targets.push((host, instance.instance_name.clone()));
+        }
+    }
+
+    if inventory.is_local() {
+        return local_logs(&targets, options);
+    }
+
+    let mut sessions: HashMap<String, Session> = connect(inventory, &targets)?;
+    let since: Vec<Option<String>> = vec![options.since.clone(); targets.len()];
+    let mut streams: Vec<LogStream> = open_streams(
+        &sessions,
+        &targets,
+        inventory.is_multi_host(),
+        options.follow,
+        &since,
+        options.tail.as_deref(),
+    )?;
+
+    let mut attempts: u32 = 0;
+    loop {
+        match pump_streams(&sessions, &mut streams, options) {
+            Ok(()) => return Ok(()),
+            Err(err) if !options.follow => return Err(err),
+            Err(err) => {
+                attempts += 1;
+                if attempts > MAX_RECONNECT_ATTEMPTS {
+                    return Err(err.context("Conexão SSH perdida"));
+                }
+
+                warn!(
+                    "Conexão SSH perdida ({err}), reconectando \
+                     ({attempts}/{MAX_RECONNECT_ATTEMPTS})"
+                );
+                thread::sleep(Duration::from_secs(2));
+
+                // Cada stream retoma da última linha que leu, para não perder
+                // nem repetir o que foi escrito durante a queda
+                let pending: Vec<&LogStream> =
+                    streams.iter().filter(|stream| !stream.done).collect();
+                let resume: Vec<(String, String)> = pending
+                    .iter()
+                    .map(|stream| (stream.host.clone(), stream.instance_name.clone()))
+                    .collect();
+                let since: Vec<Option<String>> = pending
+                    .iter()
+                    .map(|stream| stream.last_timestamp.clone())
+                    .collect();
+
+                let reconnected = connect(inventory, &resume).and_then(|new_sessions| {
+                    let new_streams = open_streams(
+                        &new_sessions,
+                        &resume,
+                        inventory.is_multi_host(),
+                        true,
+                        &since,
+                        None
+                    )?;
+                    Ok((new_sessions, new_streams))
+                });
+
+                match reconnected {
+                    Ok((new_sessions, mut new_streams)) => {
+                        for (stream, since) in new_streams.iter_mut().zip(since) {
+                            stream.resumed_from = since;
+                        }
+                        sessions = new_sessions;
+                        streams = new_streams;
+                        attempts = 0;
+                    }
+                    Err(err) => warn!("Falha ao reconectar: {err}"),
+                }
+            }
+        }
+    }
+}
+
+
+/// Uma conexão por host dos alvos
+fn connect(
+    inventory: &Inventory,
+    targets: &[(String, String)],
+) -> anyhow::Result<HashMap<String, Session>> {
+    let mut sessions: HashMap<String, Session> = HashMap::new();
+    for (host, _) in targets {
+        if !sessions.contains_key(host) {
+            sessions.insert(host.clone(), inventory.ssh_session(host)?);
+        }
+    }
+
+    Ok(sessions)
+}
+
+
+fn open_streams(
+    sessions: &HashMap<String, Session>,
+    targets: &[(String, String)],
+    show_host: bool,
+    follow: bool,
+    since: &[Option<String>],
+    tail: Option<&str>,
+) -> anyhow::Result<Vec<LogStream>> {
+
+    for session in sessions.values() {
+        session.set_blocking(true);
+    }
+
+    // Sem nenhuma linha lida, uma queda retoma a partir da abertura
+    let opened_at: String = unix_timestamp(SystemTime::now())?;
+
+    let mut streams: Vec<LogStream> = Vec::new();
+    let prefixes: Vec<String> = prefixes(targets, show_host);
+    for (((host, instance_name), prefix), since) in targets.iter().zip(prefixes).zip(since) {
+        let mut channel = sessions[host].channel_session()?;
+        channel.exec(&logs_command(instance_name, follow, since.as_deref(), tail, follow))?;
+
+        streams.push(LogStream {
+            host: host.clone(),
+            instance_name: instance_name.clone(),
+            prefix,
+            channel,
+            stdout_buffer: Vec::new(),
+            stderr_buffer: Vec::new(),
+            last_timestamp: follow.then(|| since.clone().unwrap_or(opened_at.clone())),
+            resumed_from: None,
+            done: false,
+            failed: false,
+        });
+    }
+
+    // Sem bloqueio para intercalar a leitura de vários canais
+    for session in sessions.values() {
+        session.set_blocking(false);
+        session.set_keepalive(true, 10);
+    }
+
+    Ok(streams)
+}
+
+
+/// No alvo local, cada `docker logs` é um processo cujas saídas são lidas
+/// por threads e impressas linha a linha
+fn local_logs(targets: &[(String, String)], options: &LogsOptions) -> anyhow::Result<()> {
+    let (sender, receiver) = mpsc::channel::<(usize, Vec<u8>)>();
+    let mut children: Vec<Child> = Vec::new();
+
+    for (index, (_, instance_name)) in targets.iter().enumerate() {
+        let command: String = logs_command(
+            instance_name,
+            options.follow,
+            options.since.as_deref(),
+            options.tail.as_deref(),
+            false
+        );
+        let mut child: Child = Command::new("sh")
+            .arg("-c")
+            .arg(&command)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        let outputs: [Box<dyn Read + Send>; 2] = [
+            Box::new(child.stdout.take().expect("Saída não capturada")),
+            Box::new(child.stderr.take().expect("Saída não capturada")),
+        ];
+        for output in outputs {
+            let sender = sender.clone();
+            thread::spawn(move || {
+                let mut reader = BufReader::new(output);
+                let mut line: Vec<u8> = Vec::new();
+                while reader.read_until(b'\n', &mut line).is_ok_and(|read| read > 0) {
+                    if sender.send((index, std::mem::take(&mut line))).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        children.push(child);
+    }
+    drop(sender);
+
+    let prefixes: Vec<String> = prefixes(targets, false);
+    for (index, mut line) in receiver {
+        print_lines(&prefixes[index], &mut line, options, true);
+    }
+
+    let mut failed: bool = false;
+    for (mut child, (_, instance_name)) in children.into_iter().zip(targets) {
+        let status = child.wait()?;
+        if !status.success() {
+            warn!("docker logs de {instance_name} terminou com erro ({status})");
+            failed = true;
+        }
+    }
+
+    if failed {
+        anyhow::bail!("Não foi possível ler os logs de todas as instâncias");
+    }
+
+    Ok(())
+}
+
+
+fn logs_command(
+    instance_name: &str,
+    follow: bool,
+    since: Option<&str>,
+    tail: Option<&str>,
+    timestamps: bool,
+) -> String {
+    let mut cmd = String::from("docker logs");
+    if follow {
+        cmd += " --follow";
+    }
+    if timestamps {
+        cmd += " --timestamps";
+    }
+    if let Some(since) = since {
+        cmd += &format!(" --since {}", escape(since.into()));
+    }
+    if let Some(tail) = tail {
+        cmd += &format!(" --tail {}", escape(tail.into()));
+    }
+    cmd += &format!(" {}", escape(instance_name.into()));
+    cmd
+}
+
+
+/// Prefixo de cada alvo, alinhado e colorido se a saída for um terminal
+fn prefixes(targets: &[(String, String)], show_host: bool) -> Vec<String> {
+    let labels: Vec<String> = targets
+        .iter()
+        .map(|(host, instance_name)| {
+            if show_host { format!("{instance_name}@{host}") } else { instance_name.clone() }
+        })
+        .collect();
+    let width: usize = labels.iter().map(String::len).max().unwrap_or(0);
+    let colored: bool = std::io::stdout().is_terminal();
+
+    labels
+        .iter()
+        .enumerate()
+        .map(|(index, label)| {
+            if colored {
+                let color = COLORS[index % COLORS.len()];
+                format!("{color}{label:<width$}{RESET} | ")
+            } else {
+                format!("{label:<width$} | ")
+            }
+        })
+        .collect()
+}
+
+
+/// Lê os canais até todos terminarem. Retorna erro se a sessão cair.
+fn pump_streams(
+    sessions: &HashMap<String, Session>,
+    streams: &mut [LogStream],
+    options: &LogsOptions,
+) -> anyhow::Result<()> {
+
+    let mut buffer = [0u8; 8192];
+    while streams.iter().any(|stream| !stream.done) {
+        let mut progressed = false;
+
+        for stream in streams.iter_mut().filter(|stream| !stream.done) {
+            let stdout = read_available(&mut stream.channel, &mut buffer)?;
+            stream.stdout_buffer.extend_from_slice(&buffer[..stdout.unwrap_or(0)]);
+
+            let stderr = read_available(&mut stream.channel.stderr(), &mut buffer)?;
+            stream.stderr_buffer.extend_from_slice(&buffer[..stderr.unwrap_or(0)]);
+
+            progressed |= stdout.unwrap_or(0) > 0 || stderr.unwrap_or(0) > 0;
+
+            stream.print_lines(options, false);
+
+            if stdout == Some(0) && stderr == Some(0) && stream.channel.eof() {
+                stream.print_lines(options, true);
+                stream.done = true;
+                stream.failed = !stream.exited_successfully(session_of(sessions, stream))?;
+            }
+        }
+
+        if !progressed {
+            // Detecta conexões mortas enquanto os containers não escrevem nada
+            for session in sessions.values() {
+                if let Err(err) = session.keepalive_send() {
+                    let err = std::io::Error::from(err);
+                    if err.kind() != ErrorKind::WouldBlock {
+                        return Err(err.into());
+                    }
+                }
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    if streams.iter().any(|stream| stream.failed) {
+        anyhow::bail!("Não foi possível ler os logs de todas as instâncias");
+    }
+
+    Ok(())
+}
+
+
+fn session_of<'a>(sessions: &'a HashMap<String, Session>, stream: &LogStream) -> &'a Session {
+    &sessions[&stream.host]
+}
+
+
+impl LogStream {
+    /// Imprime as linhas completas dos buffers (ou tudo, se `flush`). Com
+    /// timestamps, guarda o da última linha e o remove antes de imprimir.
+    fn print_lines(&mut self, options: &LogsOptions, flush: bool) {
+        for buffer in [&mut self.stdout_buffer, &mut self.stderr_buffer] {
+            for line in take_lines(buffer, flush) {
+                let mut line: &str = &line;
+                if self.last_timestamp.is_some()
+                    && let Some((timestamp, rest)) = line.split_once(' ')
+                    && is_docker_timestamp(timestamp) {
+                    // O `--since` inclui a linha do próprio timestamp
+                    if self.resumed_from.as_deref() == Some(timestamp) {
+                        continue;
+                    }
+                    self.last_timestamp = Some(timestamp.to_owned());
+                    line = rest;
+                }
+                print_line(&self.prefix, line, options);
+            }
+        }
+    }
+
+    /// Espera o fim do canal e avisa se o `docker logs` falhou, como quando o
+    /// contêiner não existe
+    fn exited_successfully(&mut self, session: &Session) -> anyhow::Result<bool> {
+        session.set_blocking(true);
+        let closed = self.channel.wait_close();
+        session.set_blocking(false);
+        closed?;
+
+        let exit_status: i32 = self.channel.exit_status()?;
+        if exit_status != 0 {
+            warn!(
+                "docker logs de {} em `{}` terminou com erro ({exit_status})",
+                self.instance_name,
+                self.host
+            );
+        }
+
+        Ok(exit_status == 0)
+    }
+}
+
+
+/// Timestamp RFC 3339 do `docker logs --timestamps` (2024-05-01T12:00:00.1Z)
+fn is_docker_timestamp(text: &str) -> bool {
+    text.len() >= 20 && text.as_bytes()[4] == b'-' && text.as_bytes()[10] == b'T'
+}
+
+
+/// Segundos desde a época com nanossegundos, aceito pelo `--since`
+fn unix_timestamp(time: SystemTime) -> anyhow::Result<String> {
+    let elapsed: Duration = time.duration_since(UNIX_EPOCH)?;
+    Ok(format!("{}.{:09}", elapsed.as_secs(), elapsed.subsec_nanos()))
+}
+
+
+/// `Some(n)` com os bytes lidos (0 em EOF) ou `None` se não há dados agora
+fn read_available(reader: &mut impl Read, buffer: &mut [u8]) -> anyhow::Result<Option<usize>> {
+    match reader.read(buffer) {
+        Ok(read) => Ok(Some(read)),
+        Err(err) if err.kind() == ErrorKind::WouldBlock => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+
+/// Imprime as linhas completas do buffer (ou tudo, se `flush`), aplicando o
+/// filtro de `--grep`
+fn print_lines(prefix: &str, buffer: &mut Vec<u8>, options: &LogsOptions, flush: bool) {
+    for line in take_lines(buffer, flush) {
+        print_line(prefix, &line, options);
+    }
+}
+
+
+/// Retira do buffer as linhas completas (ou tudo, se `flush`)
+fn take_lines(buffer: &mut Vec<u8>, flush: bool) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    while let Some(end) = buffer.iter().position(|byte| *byte == b'\n') {
+        let line: Vec<u8> = buffer.drain(..=end).collect();
+        lines.push(String::from_utf8_lossy(&line[..end]).into_owned());
+    }
+
+    if flush && !buffer.is_empty() {
+        let line: Vec<u8> = std::mem::take(buffer);
+        lines.push(String::from_utf8_lossy(&line).into_owned());
+    }
+
+    lines
+}
+
+
+fn print_line(prefix: &str, line: &str, options: &LogsOptions) {
+    let line = line.trim_end_matches('\r');
+    if let Some(ref grep) = options.grep
+        && grep.is_match(line) == options.invert_match {
+        return;
+    }
+
+    println!("{prefix}{line}");
+}
@@ -1,27 +1,70 @@
+mod backend;
+mod deploy;
+mod docker;
+mod exec;
+mod hooks;
+mod hosts;
+mod instances;
+mod interpolation;
+mod lifecycle;
+mod logging;
+mod logs;
 mod models;
-#[allow(dead_code, unused)]
 mod networks;
+mod overlay;
+mod redact;
+mod remotecheck;
+mod replicas;
+mod report;
+mod scale;
+mod secrets;
 mod services;
+mod smoke;
+mod status;
+mod sync;
 mod utils;
 mod volumes;
 
 use clap::Parser;
-use serde_yaml::Value;
+use regex::Regex;
 
-use crate::models::{Cli, Commands};
-use crate::networks::handle_networks;
-use crate::services::handle_group;
+use crate::deploy::handle_deploy;
+use crate::exec::{handle_exec, handle_run, RunOptions};
+use crate::hosts::Inventory;
+use crate::lifecycle::{handle_lifecycle, DownOptions, LifecycleAction};
+use crate::logging::init_logging;
+use crate::logs::{handle_logs, LogsOptions};
+use crate::models::{Cli, Commands, Target};
+use crate::redact::{
+    is_sensitive_key,
+    redact,
+    redact_value,
+    register_secret,
+    register_sensitive_values,
+};
+use crate::scale::handle_scale;
+use crate::secrets::{handle_secrets, load_secrets};
+use crate::status::handle_status;
 use crate::utils::process_deployment_file;
-use crate::volumes::handle_volumes;
 
-fn main() -> anyhow::Result<()> {
+fn main() {
     let cli = Cli::parse();
+    init_logging(cli.verbose, cli.quiet, cli.log_format);
 
+    if let Err(err) = run(cli) {
+        // A mensagem de erro pode conter comandos com valores secretos
+        tracing::error!("{}", redact(&format!("{err:?}")));
+        std::process::exit(1);
+    }
+}
+
+fn run(cli: Cli) -> anyhow::Result<()> {
     if let Some(envs) = cli.envs {
         for env in envs {
             let pair = env.split("=").collect::<Vec<&str>>();
             let value = pair[1].to_string().trim().to_string();
             let key = pair[0].to_string().trim().to_string();
+            register_secret(&value);
 
             // É seguro fazer isso aqui porque os comandos serão executados posteriormente
             unsafe {
@@ -30,34 +73,142 @@ fn main() -> anyhow::Result<()> {
         }
     }
 
-    let ssh_config = utils::get_ssh_config(&cli.config)?;
+    if let Commands::Secrets { action } = &cli.command {
+        return handle_secrets(action, &cli.secrets, cli.secrets_key.as_deref());
+    }
 
-    match cli.command {
-        Commands::Deploy { group_name } => {
-            let deploy_yaml = process_deployment_file(&cli.config)?;
-            let deploy_map = deploy_yaml.as_mapping().unwrap();
-
-            if let Some(_) = deploy_map.get(Value::String(group_name.clone())) {
-                if ["define"].contains(&group_name.as_str()) {
-                    return Ok(());
-                } else if ["networks"].contains(&group_name.as_str()) {
-                    handle_networks(&ssh_config, deploy_map, cli.dry_run)?;
-
-                    return Ok(());
-                } else if ["volumes"].contains(&group_name.as_str()) {
-                    handle_volumes(&ssh_config, deploy_map, cli.dry_run)?;
-
-                    return Ok(());
-                } else {
-                    handle_group(&ssh_config, &group_name, deploy_map, cli.dry_run)?;
-
-                    return Ok(());
-                }
-            } else {
-                println!("Grupo não encontrado!");
+    // Os segredos ficam apenas em memória; variáveis já definidas têm prioridade
+    for (key, value) in load_secrets(&cli.secrets, cli.secrets_key.as_deref())? {
+        register_secret(&value);
+        if std::env::var_os(&key).is_none() {
+            // Seguro pelo mesmo motivo acima
+            unsafe {
+                std::env::set_var(key, value);
             }
         }
     }
 
+    let deploy_yaml = process_deployment_file(&cli.config, &cli.env_config)?;
+    let deploy_map = deploy_yaml.as_mapping().unwrap();
+
+    // Variáveis de ambiente (incluindo as do arquivo de variáveis) e valores
+    // do arquivo de deploy com nomes sensíveis também são mascarados
+    for (key, value) in std::env::vars() {
+        if is_sensitive_key(&key) {
+            register_secret(&value);
+        }
+    }
+    register_sensitive_values(&deploy_yaml);
+
+    if let Commands::Config = cli.command {
+        print!("{}", serde_yaml::to_string(&redact_value(deploy_yaml.clone()))?);
+        return Ok(());
+    }
+
+    let inventory = match cli.target {
+        Target::Ssh => Inventory::load(deploy_map, utils::get_ssh_config(&cli.env_config))?,
+        Target::Local => Inventory::local()?,
+    }
+    .with_docker(cli.docker);
+
+    match cli.command {
+        Commands::Config | Commands::Secrets { .. } => unreachable!(),
+        Commands::Deploy { group_name, all, filter, report } => {
+            handle_deploy(
+                &inventory,
+                deploy_map,
+                &group_name,
+                all,
+                &filter,
+                &report,
+                cli.dry_run
+            )?;
+        }
+        Commands::Status { group_name, format } => {
+            handle_status(&inventory, deploy_map, group_name.as_deref(), format)?;
+        }
+        Commands::Logs { selector, follow, since, tail, grep, invert_match } => {
+            let options = LogsOptions {
+                follow,
+                since,
+                tail,
+                grep: grep.as_deref().map(Regex::new).transpose()?,
+                invert_match,
+            };
+
+            handle_logs(&inventory, deploy_map, &selector, &options)?;
+        }
+        Commands::Stop { selector } => {
+            handle_lifecycle(
+                &inventory,
+                deploy_map,
+                &selector,
+                LifecycleAction::Stop,
+                &DownOptions::default(),
+                cli.dry_run
+            )?;
+        }
+        Commands::Start { selector } => {
+            handle_lifecycle(
+                &inventory,
+                deploy_map,
+                &selector,
+                LifecycleAction::Start,
+                &DownOptions::default(),
+                cli.dry_run
+            )?;
+        }
+        Commands::Restart { selector } => {
+            handle_lifecycle(
+                &inventory,
+                deploy_map,
+                &selector,
+                LifecycleAction::Restart,
+                &DownOptions::default(),
+                cli.dry_run
+            )?;
+        }
+        Commands::Down { selector, volumes, networks, yes } => {
+            handle_lifecycle(
+                &inventory,
+                deploy_map,
+                &selector,
+                LifecycleAction::Down,
+                &DownOptions { volumes, networks, yes },
+                cli.dry_run
+            )?;
+        }
+        Commands::Exec { instance, host, interactive, command } => {
+            let code = handle_exec(
+                &inventory,
+                deploy_map,
+                &instance,
+                host.as_deref(),
+                &command,
+                interactive
+            )?;
+            std::process::exit(code);
+        }
+        Commands::Run { service, instance, host, interactive, command } => {
+            let options = RunOptions { service, instance, host, command, interactive };
+            let code = handle_run(&inventory, deploy_map, &options, cli.dry_run)?;
+            std::process::exit(code);
+        }
+        Commands::Scale { targets } => {
+            handle_scale(&inventory, deploy_map, &targets, cli.dry_run)?;
+        }
+        Commands::Shell { instance, host, shell } => {
+            let code = handle_exec(
+                &inventory,
+                deploy_map,
+                &instance,
+                host.as_deref(),
+                &[shell],
+                true
+            )?;
+            std::process::exit(code);
+        }
+    }
+
     Ok(())
 }
@@ -1,32 +1,288 @@
-use clap::{Parser, Subcommand};
-use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::Duration,
+};
+
+use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum};
+use serde::{Deserialize, Deserializer};
 use serde_yaml::Mapping;
 
+/// Verificação feita depois de subir a instância. Os campos usados
+/// dependem do tipo (`type`).
 #[derive(Debug, Deserialize, Clone)]
 pub struct RemoteHealthCheck {
+    #[serde(rename = "type")]
+    pub probe: Option<ProbeType>,
     pub port: Option<i32>,
     pub endpoint: Option<String>,
+    /// http: usa https
+    pub https: Option<bool>,
+    /// http: aceita certificados inválidos (ex: autoassinados)
+    pub insecure: Option<bool>,
+    /// http: status esperado; sem ele, qualquer 2xx
+    pub status: Option<u16>,
+    /// http: regex que o corpo da resposta deve conter
+    pub body: Option<String>,
+    /// http: cabeçalhos enviados na requisição
+    pub headers: Option<BTreeMap<String, String>>,
+    /// command/exec: comando que deve terminar com sucesso
+    pub command: Option<String>,
+    /// De onde as verificações http e tcp partem
+    pub from: Option<ProbeOrigin>,
+    /// Intervalo entre as tentativas (padrão 1s)
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub interval: Option<Duration>,
+    /// Tempo máximo de cada tentativa (padrão 5s)
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub timeout: Option<Duration>,
+    /// Falhas seguidas, depois do start_period, até desistir (padrão 30)
+    pub retries: Option<u32>,
+    /// Tempo após o início em que as falhas não contam
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub start_period: Option<Duration>,
+    /// Sucessos seguidos necessários para passar (padrão 1)
+    pub success_threshold: Option<u32>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ProbeOrigin {
+    /// Da máquina que faz o deploy, no endereço do host
+    #[default]
+    Local,
+    /// Do próprio host via SSH, em localhost
+    Remote,
+}
+
+/// Lê durações como `500ms`, `5s`, `2m`, `1h`, `1m30s` ou um número de segundos
+fn deserialize_duration<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Duration>, D::Error> {
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+        Seconds(f64),
+        Text(String),
+    }
+
+    let invalid = |text: &str| serde::de::Error::custom(format!("duração inválida: {text}"));
+
+    match Option::<Raw>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(Raw::Seconds(seconds)) => Duration::try_from_secs_f64(seconds)
+            .map(Some)
+            .map_err(|_| invalid(&seconds.to_string())),
+        Some(Raw::Text(text)) => parse_duration(&text).map(Some).ok_or_else(|| invalid(&text)),
+    }
 }
 
+/// Lê uma duração como `500ms`, `5s`, `2m`, `1h`, `1m30s` ou `30` (segundos)
+pub fn parse_duration(text: &str) -> Option<Duration> {
+    let mut rest: &str = text.trim();
+    if let Ok(seconds) = rest.parse::<f64>() {
+        return Duration::try_from_secs_f64(seconds).ok();
+    }
+    if rest.is_empty() {
+        return None;
+    }
+
+    let mut seconds: f64 = 0.0;
+    while !rest.is_empty() {
+        let split: usize = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(rest.len());
+        let (number, tail) = rest.split_at(split);
+        let number: f64 = number.parse().ok()?;
+
+        let unit_length: usize = tail
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(tail.len());
+        let (unit, tail) = tail.split_at(unit_length);
+
+        seconds += match unit {
+            "ms" => number / 1000.0,
+            "s" => number,
+            "m" => number * 60.0,
+            "h" => number * 3600.0,
+            _ => return None,
+        };
+        rest = tail;
+    }
+
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ProbeType {
+    /// GET na porta publicada, a partir da máquina que faz o deploy
+    #[default]
+    Http,
+    /// Conexão TCP na porta publicada
+    Tcp,
+    /// Comando executado no host via SSH
+    Command,
+    /// Comando executado dentro do contêiner (`docker exec`)
+    Exec,
+    /// Espera o healthcheck do Docker ficar `healthy`
+    Docker,
+}
+
+/// Verificação feita depois que todas as ondas de um grupo sobem. Cada
+/// teste usa um dentre `http`, `command` e `exec`.
 #[derive(Debug, Deserialize, Clone)]
-pub struct HealthCheck {
-    pub test: Vec<String>,
-    pub interval: String,
-    pub timeout: String,
-    pub retries: i32,
+pub struct SmokeTest {
+    pub name: Option<String>,
+    /// URL requisitada a partir da máquina que faz o deploy
+    pub http: Option<String>,
+    /// http: método da requisição (padrão GET)
+    pub method: Option<String>,
+    /// http: cabeçalhos enviados na requisição
+    pub headers: Option<BTreeMap<String, String>>,
+    /// http: corpo enviado na requisição
+    pub data: Option<String>,
+    /// http: aceita certificados inválidos (ex: autoassinados)
+    pub insecure: Option<bool>,
+    /// http: status esperado; sem ele, qualquer 2xx
+    pub status: Option<u16>,
+    /// Comando executado no host via SSH
+    pub command: Option<String>,
+    /// command: host do inventário onde o comando roda
+    pub host: Option<String>,
+    /// Comando executado dentro do contêiner de `instance`
+    pub exec: Option<String>,
+    pub instance: Option<String>,
+    /// Regex que o corpo da resposta ou a saída do comando deve conter
+    pub expect: Option<String>,
+    /// Tempo máximo do teste (padrão 10s)
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub timeout: Option<Duration>,
+    #[serde(default)]
+    pub on_failure: SmokeFailure,
+}
+
+/// O que fazer quando um smoke test falha. A ordem vai da mais branda para
+/// a mais severa.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SmokeFailure {
+    /// Apenas registra a falha
+    Warn,
+    /// Marca o grupo como falho
+    #[default]
+    Fail,
+    /// Volta as instâncias do grupo aos contêineres anteriores e marca o
+    /// grupo como falho
+    Rollback,
+}
+
+/// Comandos executados em pontos do deploy e do ciclo de vida. Os de um
+/// grupo ou serviço rodam uma vez por grupo ou serviço, e os de uma
+/// instância, para cada instância; não são herdados entre os níveis.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct Hooks {
+    #[serde(default)]
+    pub pre_deploy: Vec<Hook>,
+    #[serde(default)]
+    pub post_deploy: Vec<Hook>,
+    #[serde(default)]
+    pub pre_stop: Vec<Hook>,
+    #[serde(default)]
+    pub on_failure: Vec<Hook>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
+pub struct Hook {
+    pub name: Option<String>,
+    pub command: String,
+    #[serde(default)]
+    pub target: HookTarget,
+    /// remote/container: host do inventário onde o hook roda
+    pub host: Option<String>,
+    /// Tempo máximo do hook; sem ele, não há limite
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub timeout: Option<Duration>,
+    #[serde(default)]
+    pub on_error: HookFailure,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum HookTarget {
+    /// Na máquina que faz o deploy
+    Local,
+    /// No host via SSH, no diretório do deploy
+    #[default]
+    Remote,
+    /// Num contêiner avulso (`docker run --rm`) da imagem do serviço, com o
+    /// ambiente, os volumes e a rede dele
+    Container,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum HookFailure {
+    /// Interrompe a etapa, como uma falha do deploy
+    #[default]
+    Fail,
+    /// Apenas registra a falha
+    Warn,
+}
+
+/// Healthcheck do Docker. Os campos ausentes na instância vêm do serviço e,
+/// se faltarem nos dois, valem os da imagem.
+#[derive(Debug, Deserialize, Clone)]
+pub struct HealthCheck {
+    pub test: Option<Vec<String>>,
+    pub interval: Option<String>,
+    pub timeout: Option<String>,
+    pub retries: Option<i32>,
+}
+
+/// Como as listas da instância se combinam com as do serviço
+#[derive(Debug, Deserialize, Clone, Copy, Default)]
+pub struct ListMergeOptions {
+    pub environment: Option<ListMerge>,
+    pub volumes: Option<ListMerge>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ListMerge {
+    /// A lista da instância substitui a do serviço
+    #[default]
+    Replace,
+    /// A lista da instância vem depois da do serviço
+    Append,
+    /// Itens com a mesma chave (variável ou destino do volume) são trocados
+    /// pelos da instância; os demais são acrescentados
+    Merge,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
 pub struct ContainerConfig {
     pub network_mode: Option<String>,
     pub restart: Option<String>,
     pub env_file: Option<Vec<String>>,
     pub volumes: Option<Vec<String>>,
+    /// Portas publicadas no host, no formato do `docker run -p`
+    pub ports: Option<Vec<String>>,
 
     pub environment: Option<Vec<String>>,
     pub command: Option<String>,
     pub remotecheck: Option<RemoteHealthCheck>,
     pub healthcheck: Option<HealthCheck>,
+    pub host: Option<String>,
+    pub placement: Option<Placement>,
+    /// Caminhos de env_file/volumes que já estão no host e não são enviados
+    pub remote_only: Option<Vec<String>>,
+    /// Segredos montados como arquivos em /run/secrets/<nome>
+    pub secrets: Option<BTreeMap<String, String>>,
+    /// Dono (`UID[:GID]`) dos arquivos de segredos no host; padrão root
+    pub secrets_owner: Option<String>,
+    pub list_merge: Option<ListMergeOptions>,
+    pub hooks: Option<Hooks>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -36,10 +292,42 @@ pub struct ServiceConfig {
     pub restart: Option<String>,
     pub env_file: Option<Vec<String>>,
     pub volumes: Option<Vec<String>>,
+    pub ports: Option<Vec<String>>,
     pub environment: Option<Vec<String>>,
     pub depends_on: Option<Vec<String>>,
     pub instances: Mapping,
+    pub command: Option<String>,
     pub remotecheck: Option<RemoteHealthCheck>,
+    pub healthcheck: Option<HealthCheck>,
+    pub host: Option<String>,
+    pub placement: Option<Placement>,
+    pub remote_only: Option<Vec<String>>,
+    pub secrets: Option<BTreeMap<String, String>>,
+    pub secrets_owner: Option<String>,
+    pub list_merge: Option<ListMergeOptions>,
+    pub hooks: Option<Hooks>,
+}
+
+/// Restringe os hosts onde uma instância roda: pelos nomes no inventário
+/// (`hosts:`) e/ou pelos labels dos hosts
+#[derive(Debug, Deserialize, Clone)]
+pub struct Placement {
+    pub hosts: Option<Vec<String>>,
+    pub labels: Option<HashMap<String, String>>,
+}
+
+/// Um host do inventário `hosts:` do arquivo de deploy. Usuário, senha e
+/// diretório ausentes são herdados de SSH_USER, SSH_PASSWORD e DIR.
+#[derive(Debug, Deserialize, Clone)]
+pub struct HostConfig {
+    pub address: String,
+    pub port: Option<u16>,
+    pub user: Option<String>,
+    pub password: Option<String>,
+    pub key_file: Option<String>,
+    pub dir: Option<String>,
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
 }
 
 #[allow(dead_code)]
@@ -70,15 +358,37 @@ pub struct Cli {
     /// Simula a execução sem aplicar mudanças
     #[arg(short, long)]
     pub dry_run: bool,
-    /// Define o arquivo de configuração a ser usado
+    /// Define o arquivo de configuração a ser usado. Pode ser repetido: os
+    /// arquivos são mesclados em ordem (ex: -c base.yaml -c prod.yaml)
     #[arg(short, long, default_value = "deploy.yaml")]
-    pub config: String,
+    pub config: Vec<String>,
     /// Define uma lista de variáveis de ambiente a ser usadas
     #[arg(short, long)]
     pub envs: Option<Vec<String>>,
     /// Define o arquivo de configuração de variáveis de ambiente a ser usado
-    #[arg(short, long, default_value = "infra.secrets.env")]
+    #[arg(long, default_value = "infra.secrets.env")]
     pub env_config: String,
+    /// Arquivo de segredos criptografado com age (lido apenas se existir)
+    #[arg(long, default_value = "infra.secrets.env.age")]
+    pub secrets: String,
+    /// Arquivo de identidade age usado no lugar da senha (ou DDR_SECRETS_KEY)
+    #[arg(long)]
+    pub secrets_key: Option<String>,
+    /// Aumenta o detalhamento dos logs (-v: comandos remotos, -vv: saída deles)
+    #[arg(short, long, action = ArgAction::Count)]
+    pub verbose: u8,
+    /// Mostra apenas avisos e erros nos logs
+    #[arg(short, long, conflicts_with = "verbose")]
+    pub quiet: bool,
+    /// Formato dos logs, escritos na saída de erro
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+    /// Onde os comandos são executados: nos hosts via SSH ou na máquina local
+    #[arg(long, value_enum, default_value_t = Target::Ssh)]
+    pub target: Target,
+    /// Como o deploy fala com o Docker: pelo CLI ou pela API do Docker Engine
+    #[arg(long, value_enum, default_value_t = DockerMode::Cli)]
+    pub docker: DockerMode,
 }
 
 #[derive(Subcommand)]
@@ -86,30 +396,349 @@ pub enum Commands {
     #[command(
         about = "Executa o processo de deploy para um grupo",
         long_about = "Este subcomando permite rodar o processo de deploy \
-                      para um grupo específico definido no arquivo de configuração. \
+                      para um ou mais grupos definidos no arquivo de configuração, \
+                      ou para todos eles com --all. \
                       Pode simular a execução sem aplicar mudanças (dry-run)."
     )]
     Deploy {
-        /// Seleciona o grupo a ser processado
+        /// Seleciona os grupos a serem processados
+        #[arg(
+            short,
+            long,
+            num_args = 1..,
+            required_unless_present = "all",
+            conflicts_with = "all"
+        )]
+        group_name: Vec<String>,
+        /// Processa todos os grupos: networks e volumes primeiro, depois
+        /// os grupos de serviços na ordem das dependências entre eles
+        #[arg(short, long)]
+        all: bool,
+        #[command(flatten)]
+        filter: DeployFilter,
+        /// Grava o relatório do deploy (JUnit XML se terminar em .xml,
+        /// senão JSON). Pode ser repetido.
+        #[arg(long, value_name = "FILE")]
+        report: Vec<String>,
+    },
+    #[command(
+        about = "Mostra a configuração final, mesclada e com variáveis substituídas",
+        long_about = "Mescla os arquivos de configuração informados (e os que eles \
+                      estendem com extends:), substitui as variáveis e imprime o \
+                      resultado em YAML."
+    )]
+    Config,
+    #[command(
+        alias = "ps",
+        about = "Mostra o estado dos containers declarados no host remoto",
+        long_about = "Para cada instância declarada no arquivo de configuração, \
+                      mostra se o container existe, seu estado, uptime, imagem \
+                      (comparada à esperada), saúde e número de restarts. \
+                      Containers no host que não estão declarados são listados \
+                      como deriva."
+    )]
+    Status {
+        /// Limita o status a um grupo
+        #[arg(short, long)]
+        group_name: Option<String>,
+        /// Formato de saída
+        #[arg(short, long, value_enum, default_value_t = OutputFormat::Table)]
+        format: OutputFormat,
+    },
+    #[command(
+        about = "Mostra os logs de instâncias declaradas",
+        long_about = "Mostra os logs (docker logs) de uma instância, de um serviço \
+                      ou de um grupo inteiro. Os logs de várias instâncias são \
+                      intercalados, com o nome da instância como prefixo. Em modo \
+                      --follow, reconecta se a sessão SSH cair."
+    )]
+    Logs {
+        #[command(flatten)]
+        selector: InstanceSelector,
+        /// Continua acompanhando os logs
+        #[arg(short, long)]
+        follow: bool,
+        /// Mostra apenas logs a partir de um instante (ex: 10m, 2025-08-01T10:00:00)
+        #[arg(long)]
+        since: Option<String>,
+        /// Número de linhas do fim dos logs a mostrar por instância
         #[arg(short, long)]
-        group_name: String,
+        tail: Option<String>,
+        /// Mostra apenas as linhas que casam com a expressão regular
+        #[arg(long)]
+        grep: Option<String>,
+        /// Inverte o filtro de --grep, mostrando as linhas que não casam
+        #[arg(long, requires = "grep")]
+        invert_match: bool,
+    },
+    #[command(about = "Para as instâncias selecionadas, na ordem inversa das dependências")]
+    Stop {
+        #[command(flatten)]
+        selector: InstanceSelector,
+    },
+    #[command(about = "Inicia as instâncias selecionadas, na ordem das dependências")]
+    Start {
+        #[command(flatten)]
+        selector: InstanceSelector,
+    },
+    #[command(about = "Reinicia as instâncias selecionadas, na ordem das dependências")]
+    Restart {
+        #[command(flatten)]
+        selector: InstanceSelector,
+    },
+    #[command(
+        about = "Remove as instâncias selecionadas",
+        long_about = "Remove os containers das instâncias selecionadas na ordem \
+                      inversa das dependências. Opcionalmente remove também os \
+                      volumes e as redes declarados, após confirmação."
+    )]
+    Down {
+        #[command(flatten)]
+        selector: InstanceSelector,
+        /// Remove também os volumes declarados (apaga os dados!)
+        #[arg(long)]
+        volumes: bool,
+        /// Remove também as redes declaradas
+        #[arg(long)]
+        networks: bool,
+        /// Não pede confirmação para remover volumes e redes
+        #[arg(short, long)]
+        yes: bool,
+    },
+    #[command(
+        about = "Executa um comando dentro de uma instância declarada",
+        long_about = "Executa um comando dentro do container de uma instância \
+                      declarada no arquivo de configuração (docker exec). Com -t, \
+                      aloca um terminal e encaminha o terminal local. O código de \
+                      saída é o do comando executado no container."
+    )]
+    Exec {
+        /// Instância onde o comando será executado
+        instance: String,
+        /// Host da instância, quando ela roda em mais de um
+        #[arg(long)]
+        host: Option<String>,
+        /// Aloca um terminal e roda o comando de forma interativa
+        #[arg(short = 't', long)]
+        interactive: bool,
+        /// Comando a executar
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true, required = true)]
+        command: Vec<String>,
+    },
+    #[command(
+        about = "Executa um container avulso com a configuração de um serviço",
+        long_about = "Executa um container avulso (docker run --rm) com a imagem, \
+                      env_file, environment, rede, volumes e segredos de um serviço \
+                      declarado (ou de uma das suas instâncias, com --instance), \
+                      útil para comandos de manutenção. O container recebe um nome \
+                      único e o código de saída é o do comando executado nele."
+    )]
+    Run {
+        /// Serviço cuja imagem e configuração são usadas
+        service: String,
+        /// Usa a configuração desta instância do serviço
+        #[arg(long)]
+        instance: Option<String>,
+        /// Host onde o container roda (padrão: o do serviço ou da instância)
+        #[arg(long)]
+        host: Option<String>,
+        /// Aloca um terminal e roda o comando de forma interativa
+        #[arg(short = 't', long)]
+        interactive: bool,
+        /// Comando a executar no lugar do configurado no serviço
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+    #[command(
+        about = "Ajusta o número de réplicas de serviços com template de instância",
+        long_about = "Ajusta o número de réplicas de serviços com template de instância, \
+                      como `ddr scale api=6`. Cria as réplicas que faltam e remove as de \
+                      índice maior, sem mexer nas demais. O replicas do arquivo de \
+                      deploy não é alterado."
+    )]
+    Scale {
+        /// Réplicas desejadas por serviço, como `api=6`
+        #[arg(required = true, value_name = "SERVIÇO=N")]
+        targets: Vec<String>,
+    },
+    #[command(
+        about = "Abre um shell interativo dentro de uma instância declarada"
+    )]
+    Shell {
+        /// Instância onde o shell será aberto
+        instance: String,
+        /// Host da instância, quando ela roda em mais de um
+        #[arg(long)]
+        host: Option<String>,
+        /// Shell a executar no container
+        #[arg(long, default_value = "sh")]
+        shell: String,
+    },
+    #[command(
+        about = "Gerencia o arquivo de segredos criptografado",
+        long_about = "O arquivo de segredos tem o formato de um .env criptografado \
+                      com age, usando uma senha (DDR_SECRETS_PASSPHRASE ou \
+                      digitada) ou um arquivo de identidade (--secrets-key ou \
+                      DDR_SECRETS_KEY). Ele é descriptografado só em memória e suas \
+                      variáveis ficam disponíveis para a conexão SSH e para as \
+                      expressões ${VAR} do arquivo de configuração."
+    )]
+    Secrets {
+        #[command(subcommand)]
+        action: SecretsCommand,
     },
 }
 
+#[derive(Subcommand)]
+pub enum SecretsCommand {
+    /// Abre o arquivo de segredos descriptografado no $EDITOR e o criptografa de novo
+    Edit {
+        /// Arquivo de segredos (padrão: o de --secrets)
+        file: Option<String>,
+    },
+    /// Criptografa um arquivo .env
+    Encrypt {
+        /// Arquivo em texto puro
+        input: String,
+        /// Arquivo de saída (padrão: <input>.age)
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Descriptografa o arquivo de segredos
+    Decrypt {
+        /// Arquivo de segredos (padrão: o de --secrets)
+        file: Option<String>,
+        /// Arquivo de saída (padrão: saída padrão)
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Ssh,
+    Local,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DockerMode {
+    Cli,
+    Api,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Table,
+    Json,
+}
+
+/// Filtros para fazer o deploy de apenas parte de um grupo
+#[derive(Args, Debug, Clone, Default)]
+pub struct DeployFilter {
+    /// Limita o deploy aos serviços informados (aceita glob, ex: `api*`)
+    #[arg(short, long = "service")]
+    pub services: Vec<String>,
+    /// Limita o deploy às instâncias informadas (aceita glob, ex: `api[12]`)
+    #[arg(short, long = "instance")]
+    pub instances: Vec<String>,
+    /// Inclui as dependências (depends_on) transitivas dos serviços selecionados
+    #[arg(long)]
+    pub with_deps: bool,
+}
+
+impl DeployFilter {
+    pub fn is_empty(&self) -> bool {
+        self.services.is_empty() && self.instances.is_empty()
+    }
+}
+
+/// Seleção de instâncias declaradas por grupo, serviço e instância
+#[derive(Args, Debug, Clone, Default)]
+pub struct InstanceSelector {
+    /// Limita a seleção a um grupo
+    #[arg(short, long)]
+    pub group_name: Option<String>,
+    /// Limita a seleção aos serviços informados (aceita glob, ex: `api*`)
+    #[arg(short, long = "service")]
+    pub services: Vec<String>,
+    /// Limita a seleção às instâncias informadas (aceita glob, ex: `api[12]`)
+    #[arg(short, long = "instance")]
+    pub instances: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
 pub struct SSHConfig {
     pub user: String,
     pub host: String,
+    pub port: u16,
     pub password: String,
+    pub key_file: Option<String>,
     pub from_dir: String,
 }
 
 impl SSHConfig {
     pub fn new(user: String, host: String, password: String, from_dir: String) -> Self {
         SSHConfig {
-            user: user,
-            host: host,
-            password: password,
-            from_dir: from_dir,
+            user,
+            host,
+            port: 22,
+            password,
+            key_file: None,
+            from_dir,
         }
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_with_a_unit() {
+        assert_eq!(parse_duration("30s"), Some(Duration::from_secs(30)));
+        assert_eq!(parse_duration("500ms"), Some(Duration::from_millis(500)));
+        assert_eq!(parse_duration("2m"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_duration("1h"), Some(Duration::from_secs(3600)));
+        assert_eq!(parse_duration("1.5s"), Some(Duration::from_millis(1500)));
+        assert_eq!(parse_duration(" 5s "), Some(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn compound_durations() {
+        assert_eq!(parse_duration("1m30s"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration("1h2m3s"), Some(Duration::from_secs(3723)));
+        assert_eq!(parse_duration("1s500ms"), Some(Duration::from_millis(1500)));
+    }
+
+    #[test]
+    fn bare_numbers_are_seconds() {
+        assert_eq!(parse_duration("30"), Some(Duration::from_secs(30)));
+        assert_eq!(parse_duration("0.25"), Some(Duration::from_millis(250)));
+        assert_eq!(parse_duration("0"), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn invalid_durations() {
+        for text in ["", "s", "abc", "10x", "5 s", "1m30", "-5s", "1..5s", "5sm"] {
+            assert_eq!(parse_duration(text), None, "{text}");
+        }
+    }
+
+    #[test]
+    fn durations_are_deserialized_from_text_or_seconds() {
+        let check: RemoteHealthCheck =
+            serde_yaml::from_str("{interval: 1m30s, timeout: 2}").unwrap();
+        assert_eq!(check.interval, Some(Duration::from_secs(90)));
+        assert_eq!(check.timeout, Some(Duration::from_secs(2)));
+
+        let err = serde_yaml::from_str::<RemoteHealthCheck>("{interval: logo}").unwrap_err();
+        assert!(err.to_string().contains("duração inválida: logo"));
+    }
+}
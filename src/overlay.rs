@@ -0,0 +1,197 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde_yaml::{from_str, Mapping, Value};
+
+
+/// Tag que faz uma lista ser acrescentada à lista base em vez de substituí-la
+const APPEND_TAG: &str = "!append";
+/// Tag explícita para o comportamento padrão de listas: substituir
+const REPLACE_TAG: &str = "!replace";
+
+
+/// Lê os arquivos de configuração e os mescla em ordem, resolvendo `extends:`.
+/// Retorna o documento ainda sem a substituição de variáveis.
+pub fn load_merged(file_paths: &[String]) -> anyhow::Result<Value> {
+    let mut merged: Value = Value::Mapping(Mapping::new());
+    for file_path in file_paths {
+        let value = load_with_extends(Path::new(file_path), &mut Vec::new())?;
+        merged = merge(merged, value);
+    }
+
+    Ok(merged)
+}
+
+
+/// Carrega um arquivo já mesclado sobre os arquivos que ele estende. Os
+/// caminhos em `extends:` são relativos ao diretório do arquivo.
+fn load_with_extends(file_path: &Path, visiting: &mut Vec<PathBuf>) -> anyhow::Result<Value> {
+    let canonical: PathBuf = fs::canonicalize(file_path)
+        .map_err(|err| anyhow::anyhow!("Não foi possível ler {}: {err}", file_path.display()))?;
+
+    if visiting.contains(&canonical) {
+        anyhow::bail!("extends cíclico envolvendo {}", file_path.display());
+    }
+    visiting.push(canonical);
+
+    let mut value: Value = from_str(&fs::read_to_string(file_path)?)?;
+
+    let extends: Vec<String> = match value.as_mapping_mut().and_then(|map| map.remove("extends")) {
+        None => Vec::new(),
+        Some(Value::String(base)) => vec![base],
+        Some(bases) => serde_yaml::from_value(bases)
+            .map_err(|_| anyhow::anyhow!("extends deve ser um arquivo ou uma lista de arquivos"))?,
+    };
+
+    let directory: &Path = file_path.parent().unwrap_or(Path::new(""));
+    let mut merged: Value = Value::Mapping(Mapping::new());
+    for base in extends {
+        merged = merge(merged, load_with_extends(&directory.join(base), visiting)?);
+    }
+
+    visiting.pop();
+
+    Ok(merge(merged, value))
+}
+
+
+/// Mescla `overlay` sobre `base`: mappings são mesclados recursivamente,
+/// listas e escalares são substituídos, e listas marcadas com `!append` são
+/// acrescentadas à lista base.
+fn merge(base: Value, overlay: Value) -> Value {
+    match (base, overlay) {
+        (Value::Mapping(mut base), Value::Mapping(overlay)) => {
+            for (key, value) in overlay {
+                // Mescla no lugar para preservar a ordem das chaves da base
+                match base.get_mut(&key) {
+                    Some(slot) => *slot = merge(std::mem::take(slot), value),
+                    None => {
+                        base.insert(key, strip_directives(value));
+                    }
+                }
+            }
+            Value::Mapping(base)
+        }
+        (base, Value::Tagged(tagged)) if tagged.tag == APPEND_TAG => {
+            let mut items: Vec<Value> = match base {
+                Value::Sequence(items) => items,
+                _ => Vec::new(),
+            };
+            if let Value::Sequence(appended) = strip_directives(tagged.value) {
+                items.extend(appended);
+            }
+            Value::Sequence(items)
+        }
+        (_, overlay) => strip_directives(overlay),
+    }
+}
+
+
+/// Remove as tags de diretiva (`!append`, `!replace`) de um valor
+fn strip_directives(value: Value) -> Value {
+    match value {
+        Value::Tagged(tagged) if tagged.tag == APPEND_TAG || tagged.tag == REPLACE_TAG => {
+            strip_directives(tagged.value)
+        }
+        Value::Mapping(map) => Value::Mapping(
+            map.into_iter()
+                .map(|(key, value)| (key, strip_directives(value)))
+                .collect()
+        ),
+        Value::Sequence(items) => Value::Sequence(items.into_iter().map(strip_directives).collect()),
+        other => other,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn merged(base: &str, overlay: &str) -> Value {
+        merge(from_str(base).unwrap(), from_str(overlay).unwrap())
+    }
+
+    fn yaml(text: &str) -> Value {
+        from_str(text).unwrap()
+    }
+
+    #[test]
+    fn nested_mappings_are_merged_key_by_key() {
+        let value = merged(
+            "app: {api: {image: api:1, restart: always}, db: {image: pg}}",
+            "app: {api: {image: api:2, network_mode: host}}",
+        );
+
+        assert_eq!(
+            value,
+            yaml(
+                "app: {api: {image: api:2, restart: always, network_mode: host}, \
+                 db: {image: pg}}"
+            )
+        );
+    }
+
+    #[test]
+    fn base_key_order_is_preserved() {
+        let value = merged("{a: 1, b: 2, c: 3}", "{c: 30, a: 10, d: 4}");
+        let keys: Vec<&str> = value
+            .as_mapping()
+            .unwrap()
+            .keys()
+            .filter_map(Value::as_str)
+            .collect();
+
+        assert_eq!(keys, ["a", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn scalars_are_overridden() {
+        assert_eq!(
+            merged("{port: 80, name: api}", "{port: 8080}"),
+            yaml("{port: 8080, name: api}")
+        );
+        assert_eq!(merged("{api: {image: x}}", "{api: null}"), yaml("{api: null}"));
+        assert_eq!(merged("{api: [a]}", "{api: other}"), yaml("{api: other}"));
+    }
+
+    #[test]
+    fn lists_are_replaced_by_default() {
+        assert_eq!(merged("{env: [A=1, B=2]}", "{env: [C=3]}"), yaml("{env: [C=3]}"));
+    }
+
+    #[test]
+    fn append_extends_the_base_list() {
+        let value = merged("{env: [A=1, B=2]}", "{env: !append [C=3]}");
+        assert_eq!(value, yaml("{env: [A=1, B=2, C=3]}"));
+    }
+
+    #[test]
+    fn append_without_a_base_list_starts_a_new_one() {
+        assert_eq!(merged("{}", "{env: !append [C=3]}"), yaml("{env: [C=3]}"));
+        assert_eq!(merged("{env: x}", "{env: !append [C=3]}"), yaml("{env: [C=3]}"));
+    }
+
+    #[test]
+    fn replace_replaces_the_list_and_is_stripped() {
+        let value = merged("{env: [A=1]}", "{env: !replace [B=2]}");
+        assert_eq!(value, yaml("{env: [B=2]}"));
+    }
+
+    #[test]
+    fn directives_in_new_keys_are_stripped() {
+        let value = merged("{}", "{api: {env: !append [A=1], volumes: !replace [/data]}}");
+        assert_eq!(value, yaml("{api: {env: [A=1], volumes: [/data]}}"));
+    }
+
+    #[test]
+    fn overlays_are_applied_in_order() {
+        let value = merge(
+            merged("{env: [A=1]}", "{env: !append [B=2]}"),
+            yaml("{env: !append [C=3]}"),
+        );
+        assert_eq!(value, yaml("{env: [A=1, B=2, C=3]}"));
+    }
+}
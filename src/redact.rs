@@ -0,0 +1,237 @@
+use std::sync::{LazyLock, RwLock};
+
+use regex::Regex;
+use serde_yaml::Value;
+
+
+/// Texto que substitui os valores sensíveis
+const MASK: &str = "******";
+
+/// Valores mais curtos que isso não são mascarados: trocariam trechos comuns
+/// da saída sem proteger nada
+const MIN_SECRET_LENGTH: usize = 4;
+
+/// Valores conhecidos como secretos: os do arquivo de segredos, os de
+/// `--envs`, os de chaves sensíveis e os segredos das instâncias
+static SECRET_VALUES: RwLock<Vec<String>> = RwLock::new(Vec::new());
+
+/// Nomes de variáveis e chaves cujo valor é sensível
+static SENSITIVE_KEY: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)(pass|secret|token|api_?key|private_?key|credential)").unwrap()
+});
+
+/// Chaves que casam com `SENSITIVE_KEY` mas não guardam nada secreto
+const NON_SENSITIVE_KEYS: [&str; 1] = ["secrets_owner"];
+
+/// Atribuições `CHAVE=valor` com chave sensível (ex: `-e DB_PASSWORD=...`)
+static SENSITIVE_ASSIGNMENT: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?i)\b([\w.-]*(?:pass|secret|token|api_?key|private_?key|credential)[\w.-]*)=([^\s'\x22]+)"
+    )
+    .unwrap()
+});
+
+
+/// Registra um valor para ser mascarado em toda a saída
+pub fn register_secret(value: &str) {
+    if value.chars().count() < MIN_SECRET_LENGTH {
+        return;
+    }
+
+    let mut values = SECRET_VALUES.write().unwrap_or_else(|err| err.into_inner());
+    if !values.iter().any(|known| known == value) {
+        values.push(value.to_owned());
+        // Os mais longos primeiro, para não deixar pedaços de um valor que
+        // contém outro
+        values.sort_by_key(|known| std::cmp::Reverse(known.len()));
+    }
+}
+
+
+pub fn is_sensitive_key(key: &str) -> bool {
+    SENSITIVE_KEY.is_match(key) && !NON_SENSITIVE_KEYS.contains(&key)
+}
+
+
+/// Mascara os valores registrados e as atribuições de chaves sensíveis
+pub fn redact(text: &str) -> String {
+    let mut redacted: String = SENSITIVE_ASSIGNMENT
+        .replace_all(text, format!("${{1}}={MASK}"))
+        .into_owned();
+
+    let values = SECRET_VALUES.read().unwrap_or_else(|err| err.into_inner());
+    for value in values.iter() {
+        if redacted.contains(value.as_str()) {
+            redacted = redacted.replace(value.as_str(), MASK);
+        }
+    }
+
+    redacted
+}
+
+
+/// Registra os valores de chaves sensíveis e de `secrets:` do documento
+pub fn register_sensitive_values(value: &Value) {
+    match value {
+        Value::Sequence(items) => items.iter().for_each(register_sensitive_values),
+        Value::Mapping(map) => {
+            for (key, value) in map {
+                if key.as_str().is_some_and(|key| key == "secrets" || is_sensitive_key(key)) {
+                    register_scalars(value);
+                } else {
+                    register_sensitive_values(value);
+                }
+            }
+        }
+        Value::Tagged(tagged) => register_sensitive_values(&tagged.value),
+        _ => {}
+    }
+}
+
+
+fn register_scalars(value: &Value) {
+    match value {
+        Value::String(text) => register_secret(text),
+        Value::Number(number) => register_secret(&number.to_string()),
+        Value::Sequence(items) => items.iter().for_each(register_scalars),
+        Value::Mapping(map) => map.values().for_each(register_scalars),
+        _ => {}
+    }
+}
+
+
+/// Mascara um documento YAML: os valores de chaves sensíveis e de `secrets:`
+/// e, nos demais textos, o mesmo que `redact`
+pub fn redact_value(value: Value) -> Value {
+    match value {
+        Value::String(text) => Value::String(redact(&text)),
+        Value::Sequence(items) => Value::Sequence(items.into_iter().map(redact_value).collect()),
+        Value::Mapping(map) => Value::Mapping(
+            map.into_iter()
+                .map(|(key, value)| {
+                    let sensitive = key
+                        .as_str()
+                        .is_some_and(|key| key == "secrets" || is_sensitive_key(key));
+
+                    let value = if sensitive { mask_scalars(value) } else { redact_value(value) };
+                    (key, value)
+                })
+                .collect()
+        ),
+        Value::Tagged(mut tagged) => {
+            tagged.value = redact_value(tagged.value);
+            Value::Tagged(tagged)
+        }
+        other => other,
+    }
+}
+
+
+/// Troca todos os escalares do valor pela máscara
+fn mask_scalars(value: Value) -> Value {
+    match value {
+        Value::Sequence(items) => Value::Sequence(items.into_iter().map(mask_scalars).collect()),
+        Value::Mapping(map) => Value::Mapping(
+            map.into_iter()
+                .map(|(key, value)| (key, mask_scalars(value)))
+                .collect()
+        ),
+        Value::Null => Value::Null,
+        _ => Value::String(MASK.to_owned()),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Os valores registrados são globais; cada teste usa valores próprios
+
+    #[test]
+    fn registered_values_are_masked() {
+        register_secret("s3cr3t-registered");
+        assert_eq!(redact("senha: s3cr3t-registered."), "senha: ******.");
+    }
+
+    #[test]
+    fn overlapping_values_are_masked_whole() {
+        register_secret("overlap-abc");
+        register_secret("overlap-abc-def");
+
+        assert_eq!(redact("a=overlap-abc-def b=overlap-abc"), "a=****** b=******");
+    }
+
+    #[test]
+    fn short_values_are_not_registered() {
+        register_secret("x9z");
+        assert_eq!(redact("x9z x9z"), "x9z x9z");
+
+        register_secret("x9z7");
+        assert_eq!(redact("x9z7"), MASK);
+    }
+
+    #[test]
+    fn sensitive_assignments_are_masked() {
+        assert_eq!(
+            redact("docker run -e DB_PASSWORD=abc -e API_KEY=k1 -e PORT=8080 app"),
+            "docker run -e DB_PASSWORD=****** -e API_KEY=****** -e PORT=8080 app"
+        );
+        assert_eq!(redact("-e 'GITHUB_TOKEN=t0k'"), "-e 'GITHUB_TOKEN=******'");
+    }
+
+    #[test]
+    fn sensitive_keys_are_recognized() {
+        assert!(is_sensitive_key("DB_PASSWORD"));
+        assert!(is_sensitive_key("apiKey"));
+        assert!(is_sensitive_key("private_key"));
+        assert!(!is_sensitive_key("image"));
+        assert!(!is_sensitive_key("secrets_owner"));
+    }
+
+    #[test]
+    fn config_output_masks_sensitive_keys_and_secrets() {
+        let document: Value = serde_yaml::from_str(
+            "
+define: {db_password: pw-from-define, image: app}
+api:
+  image: app
+  secrets: {db: from-secrets, nested: [one-item]}
+  environment: [DB_PASSWORD=inline-pw, PORT=80]
+  secrets_owner: '1000:1000'
+  healthcheck: null
+",
+        )
+        .unwrap();
+
+        let redacted: Value = redact_value(document);
+        let expected: Value = serde_yaml::from_str(
+            "
+define: {db_password: '******', image: app}
+api:
+  image: app
+  secrets: {db: '******', nested: ['******']}
+  environment: [DB_PASSWORD=******, PORT=80]
+  secrets_owner: '1000:1000'
+  healthcheck: null
+",
+        )
+        .unwrap();
+
+        assert_eq!(redacted, expected);
+    }
+
+    #[test]
+    fn sensitive_values_of_the_document_are_registered() {
+        let document: Value = serde_yaml::from_str(
+            "{define: {api_token: doc-token-1}, api: {secrets: {db: doc-secret-2}}, x: doc-plain-3}"
+        )
+        .unwrap();
+
+        register_sensitive_values(&document);
+        assert_eq!(
+            redact("doc-token-1 doc-secret-2 doc-plain-3"),
+            "****** ****** doc-plain-3"
+        );
+    }
+}
@@ -0,0 +1,401 @@
+use std::{
+    net::{TcpStream, ToSocketAddrs},
+    thread,
+    time::{Duration, Instant},
+};
+
+use regex::Regex;
+use reqwest::blocking::Client;
+use shell_escape::escape;
+use tracing::{debug, info, warn};
+
+use crate::{
+    backend::Backend,
+    models::{ProbeOrigin, ProbeType, RemoteHealthCheck, SSHConfig},
+    utils::run_remote_output,
+};
+
+
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(1);
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_RETRIES: u32 = 30;
+
+/// Separa o corpo do status na saída do curl
+const CURL_STATUS_MARKER: &str = "\n__ddr_status__:";
+
+
+/// Uma verificação já montada a partir do `remotecheck`
+enum Probe {
+    Http {
+        url: String,
+        /// `None` quando a requisição parte do host, com curl
+        client: Option<Client>,
+        headers: Vec<(String, String)>,
+        insecure: bool,
+        status: Option<u16>,
+        body: Option<Regex>,
+    },
+    Tcp {
+        host: String,
+        port: u16,
+        remote: bool,
+    },
+    Command(String),
+    Exec(String),
+    Docker,
+}
+
+
+/// Quando e quantas vezes a verificação é repetida
+struct Timing {
+    interval: Duration,
+    timeout: Duration,
+    retries: u32,
+    start_period: Duration,
+    success_threshold: u32,
+}
+
+
+/// Repete a verificação da instância até ela passar `success_threshold`
+/// vezes seguidas ou falhar `retries` vezes seguidas depois do
+/// `start_period`. Retorna `false` se não houver o que verificar.
+pub fn wait_until_ready(
+    check: &RemoteHealthCheck,
+    instance_name: &str,
+    ssh_config: &SSHConfig,
+    session: &dyn Backend,
+) -> anyhow::Result<bool> {
+
+    let timing = Timing {
+        interval: check.interval.unwrap_or(DEFAULT_INTERVAL),
+        timeout: check.timeout.unwrap_or(DEFAULT_TIMEOUT),
+        retries: check.retries.unwrap_or(DEFAULT_RETRIES).max(1),
+        start_period: check.start_period.unwrap_or_default(),
+        success_threshold: check.success_threshold.unwrap_or(1).max(1),
+    };
+
+    let Some(probe) = Probe::new(check, instance_name, ssh_config, &timing)? else {
+        return Ok(false);
+    };
+
+    let started = Instant::now();
+    let mut failures: u32 = 0;
+    let mut successes: u32 = 0;
+    let mut attempt: u32 = 0;
+
+    loop {
+        attempt += 1;
+        match probe.run(instance_name, session, &timing)? {
+            Ok(()) => {
+                successes += 1;
+                failures = 0;
+                debug!(
+                    "Tentativa {attempt} de {} ok ({successes}/{})",
+                    probe.describe(),
+                    timing.success_threshold
+                );
+                if successes >= timing.success_threshold {
+                    info!(
+                        seconds = started.elapsed().as_secs_f64(),
+                        "Instância {instance_name} ok: {}",
+                        probe.describe()
+                    );
+                    return Ok(true);
+                }
+            }
+            Err(failure) => {
+                successes = 0;
+                // Falhas durante o start_period não contam
+                if started.elapsed() >= timing.start_period {
+                    failures += 1;
+                }
+                debug!(
+                    "Tentativa {attempt} de {} falhou ({failures}/{}): {failure}",
+                    probe.describe(),
+                    timing.retries
+                );
+
+                if failures >= timing.retries {
+                    anyhow::bail!(
+                        "A instância {instance_name} não passou na verificação {} \
+                         após {attempt} tentativas: {failure}",
+                        probe.describe()
+                    );
+                }
+            }
+        }
+
+        thread::sleep(timing.interval);
+    }
+}
+
+
+impl Probe {
+    fn new(
+        check: &RemoteHealthCheck,
+        instance_name: &str,
+        ssh_config: &SSHConfig,
+        timing: &Timing,
+    ) -> anyhow::Result<Option<Self>> {
+
+        let remote: bool = check.from.unwrap_or_default() == ProbeOrigin::Remote;
+        // A partir do próprio host, a instância é acessada em localhost
+        let host: &str = if remote { "localhost" } else { &ssh_config.host };
+
+        let required_command = || {
+            check.command.clone().ok_or_else(|| {
+                anyhow::anyhow!("O remotecheck da instância {instance_name} precisa de command")
+            })
+        };
+
+        let probe = match check.probe.unwrap_or_default() {
+            ProbeType::Http => {
+                // Sem porta o remotecheck não era verificado; mantém assim
+                let Some(port) = check.port else {
+                    warn!("O remotecheck da instância {instance_name} não tem port; ignorado");
+                    return Ok(None);
+                };
+
+                let scheme: &str = if check.https.unwrap_or(false) { "https" } else { "http" };
+                let endpoint: &str = check.endpoint.as_deref().unwrap_or("/");
+                let insecure: bool = check.insecure.unwrap_or(false);
+                let body: Option<Regex> = check.body
+                    .as_deref()
+                    .map(Regex::new)
+                    .transpose()
+                    .map_err(|err| {
+                        anyhow::anyhow!(
+                            "Regex body inválida no remotecheck de {instance_name}: {err}"
+                        )
+                    })?;
+
+                let client: Option<Client> = if remote {
+                    None
+                } else {
+                    Some(
+                        Client::builder()
+                            .timeout(timing.timeout)
+                            .danger_accept_invalid_certs(insecure)
+                            .build()?
+                    )
+                };
+
+                Probe::Http {
+                    url: format!("{scheme}://{host}:{port}{endpoint}"),
+                    client,
+                    headers: check.headers
+                        .iter()
+                        .flatten()
+                        .map(|(name, value)| (name.clone(), value.clone()))
+                        .collect(),
+                    insecure,
+                    status: check.status,
+                    body,
+                }
+            }
+            ProbeType::Tcp => {
+                let port = check.port
+                    .and_then(|port| u16::try_from(port).ok())
+                    .ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "O remotecheck tcp da instância {instance_name} precisa de port válida"
+                        )
+                    })?;
+                Probe::Tcp { host: host.to_owned(), port, remote }
+            }
+            ProbeType::Command => Probe::Command(required_command()?),
+            ProbeType::Exec => Probe::Exec(required_command()?),
+            ProbeType::Docker => Probe::Docker,
+        };
+
+        Ok(Some(probe))
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Probe::Http { url, client: Some(_), .. } => url.clone(),
+            Probe::Http { url, client: None, .. } => format!("{url} a partir do host"),
+            Probe::Tcp { host, port, remote: false } => format!("tcp {host}:{port}"),
+            Probe::Tcp { host, port, remote: true } => {
+                format!("tcp {host}:{port} a partir do host")
+            }
+            Probe::Command(command) => format!("`{command}` no host"),
+            Probe::Exec(command) => format!("`{command}` no contêiner"),
+            Probe::Docker => "healthcheck do Docker".to_owned(),
+        }
+    }
+
+    /// Executa uma tentativa. O erro externo interrompe as tentativas; o
+    /// interno é o motivo de uma tentativa que falhou.
+    fn run(
+        &self,
+        instance_name: &str,
+        session: &dyn Backend,
+        timing: &Timing,
+    ) -> anyhow::Result<Result<(), String>> {
+
+        // `timeout` e `curl --max-time` só aceitam segundos inteiros aqui
+        let seconds: u64 = timing.timeout.as_secs().max(1);
+
+        let result = match self {
+            Probe::Http { url, client: Some(client), headers, status, body, .. } => {
+                let mut request = client.get(url);
+                for (name, value) in headers {
+                    request = request.header(name, value);
+                }
+
+                match request.send() {
+                    Err(err) => Err(err.to_string()),
+                    Ok(response) => {
+                        let code: u16 = response.status().as_u16();
+                        match response.text() {
+                            Ok(text) => check_response(code, &text, *status, body.as_ref()),
+                            Err(err) => Err(err.to_string()),
+                        }
+                    }
+                }
+            }
+            Probe::Http { url, client: None, headers, insecure, status, body } => {
+                let mut command: String = format!(
+                    "curl -sS --max-time {seconds} -w {} ",
+                    escape(format!("{CURL_STATUS_MARKER}%{{http_code}}").into())
+                );
+                if *insecure {
+                    command += "-k ";
+                }
+                for (name, value) in headers {
+                    command += &format!("-H {} ", escape(format!("{name}: {value}").into()));
+                }
+                command += &escape(url.as_str().into());
+
+                match run_remote_output(session, &command) {
+                    Err(err) if format!("{err:#}").contains("(127)") => {
+                        anyhow::bail!("curl não encontrado no host para o remotecheck de {url}")
+                    }
+                    Err(err) => Err(format!("{err:#}")),
+                    Ok(output) => match output.rsplit_once(CURL_STATUS_MARKER) {
+                        Some((text, code)) => match code.trim().parse::<u16>() {
+                            Ok(code) => check_response(code, text, *status, body.as_ref()),
+                            Err(_) => Err(format!("status inválido do curl: {code}")),
+                        },
+                        None => Err("resposta inesperada do curl".to_owned()),
+                    },
+                }
+            }
+            Probe::Tcp { host, port, remote: false } => {
+                match (host.as_str(), *port).to_socket_addrs() {
+                    Err(err) => Err(err.to_string()),
+                    Ok(mut addresses) => {
+                        let connected = addresses.any(|address| {
+                            TcpStream::connect_timeout(&address, timing.timeout).is_ok()
+                        });
+                        if connected { Ok(()) } else { Err("conexão recusada".to_owned()) }
+                    }
+                }
+            }
+            // A conexão é aberta no próprio host
+            Probe::Tcp { host, port, remote: true } => {
+                session.probe_tcp(host, *port).map_err(|err| err.to_string())
+            }
+            Probe::Command(command) => run_remote_output(
+                session,
+                &format!("timeout {seconds} sh -c {}", escape(command.as_str().into())),
+            )
+            .map(|_| ())
+            .map_err(|err| format!("{err:#}")),
+            Probe::Exec(command) => run_remote_output(
+                session,
+                &format!(
+                    "timeout {seconds} docker exec {} sh -c {}",
+                    escape(instance_name.into()),
+                    escape(command.as_str().into())
+                ),
+            )
+            .map(|_| ())
+            .map_err(|err| format!("{err:#}")),
+            Probe::Docker => {
+                let health: String = run_remote_output(
+                    session,
+                    &format!(
+                        "docker inspect -f '{}' {}",
+                        "{{if .State.Health}}{{.State.Health.Status}}{{else}}none{{end}}",
+                        escape(instance_name.into())
+                    ),
+                )?;
+
+                match health.trim() {
+                    "healthy" => Ok(()),
+                    "none" => anyhow::bail!(
+                        "A instância {instance_name} não tem healthcheck do Docker para verificar"
+                    ),
+                    other => Err(format!("estado {other}")),
+                }
+            }
+        };
+
+        Ok(result)
+    }
+}
+
+
+/// Confere o status (qualquer 2xx, se não houver um esperado) e o corpo
+pub fn check_response(
+    code: u16,
+    text: &str,
+    status: Option<u16>,
+    body: Option<&Regex>,
+) -> Result<(), String> {
+
+    let status_ok: bool = match status {
+        Some(expected) => code == expected,
+        None => (200..300).contains(&code),
+    };
+
+    if !status_ok {
+        return Err(format!("status {code}"));
+    }
+
+    match body {
+        Some(body) if !body.is_match(text) => {
+            Err(format!("o corpo da resposta não contém `{body}`"))
+        }
+        _ => Ok(()),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+    use crate::{backend::LocalBackend, hosts::{Inventory, LOCAL_HOST}};
+
+    fn timing() -> Timing {
+        Timing {
+            interval: Duration::from_millis(10),
+            timeout: Duration::from_secs(1),
+            retries: 1,
+            start_period: Duration::ZERO,
+            success_threshold: 1,
+        }
+    }
+
+    #[test]
+    fn local_probes_reach_the_local_machine() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port: u16 = listener.local_addr().unwrap().port();
+
+        let inventory = Inventory::local().unwrap();
+        let ssh_config: &SSHConfig = inventory.ssh_config(LOCAL_HOST).unwrap();
+        let check: RemoteHealthCheck =
+            serde_yaml::from_str(&format!("{{type: tcp, port: {port}, from: local}}")).unwrap();
+
+        let probe = Probe::new(&check, "api", ssh_config, &timing()).unwrap().unwrap();
+        assert_eq!(probe.describe(), format!("tcp localhost:{port}"));
+        assert_eq!(probe.run("api", &LocalBackend, &timing()).unwrap(), Ok(()));
+
+        drop(listener);
+        assert!(probe.run("api", &LocalBackend, &timing()).unwrap().is_err());
+    }
+}
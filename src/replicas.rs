@@ -0,0 +1,303 @@
+use std::ops::RangeInclusive;
+
+use regex::Regex;
+use serde_yaml::{Mapping, Number, Value};
+
+use crate::instances::{GROUP_SETTINGS, RESERVED_GROUPS};
+
+
+/// Placeholder do índice da réplica (a partir de 1) nos templates
+pub const INDEX_PLACEHOLDER: &str = "${index}";
+
+/// Campos numéricos da configuração. Um texto com `${...}` num deles que
+/// vira um número inteiro (ex: `port: 80${index}`) passa a ser um número;
+/// nos demais campos, o valor substituído continua um texto.
+pub const NUMERIC_FIELDS: [&str; 5] =
+    ["port", "replicas", "retries", "status", "success_threshold"];
+
+
+/// Template de instância de um serviço e quantas réplicas ele declara
+pub struct ReplicaTemplate {
+    pub service_name: String,
+    /// Nome das réplicas, com o placeholder do índice
+    pub name: String,
+    pub config: Mapping,
+    pub replicas: u32,
+}
+
+
+impl ReplicaTemplate {
+    /// Lê `template:` e `replicas:` de um serviço, se ele tiver algum dos dois
+    pub fn from_service(service_name: &str, service: &Mapping) -> anyhow::Result<Option<Self>> {
+        let template: Option<&Value> = service.get("template");
+        let replicas: Option<&Value> = service.get("replicas");
+        if template.is_none() && replicas.is_none() {
+            return Ok(None);
+        }
+
+        let mut config: Mapping = match template {
+            None => Mapping::new(),
+            Some(Value::Mapping(template)) => template.clone(),
+            Some(_) => anyhow::bail!("O template do serviço {service_name} não é um mapping"),
+        };
+
+        let name: String = match config.remove("name") {
+            None => format!("{service_name}{INDEX_PLACEHOLDER}"),
+            Some(Value::String(name)) if name.contains(INDEX_PLACEHOLDER) => name,
+            Some(_) => anyhow::bail!(
+                "O name do template do serviço {service_name} precisa conter {INDEX_PLACEHOLDER}"
+            ),
+        };
+
+        let replicas: u32 = match replicas {
+            None => 1,
+            Some(value) => value
+                .as_u64()
+                .and_then(|replicas| u32::try_from(replicas).ok())
+                .ok_or_else(|| {
+                    anyhow::anyhow!("replicas inválido no serviço {service_name}")
+                })?,
+        };
+
+        Ok(Some(ReplicaTemplate { service_name: service_name.to_owned(), name, config, replicas }))
+    }
+
+    pub fn instance_name(&self, index: u32) -> String {
+        self.name.replace(INDEX_PLACEHOLDER, &index.to_string())
+    }
+
+    /// Instâncias das réplicas com os índices informados, com o placeholder
+    /// substituído em todos os valores
+    pub fn instances(&self, indexes: RangeInclusive<u32>) -> Vec<(String, Value)> {
+        indexes
+            .map(|index| {
+                let config = substitute(Value::Mapping(self.config.clone()), index);
+                (self.instance_name(index), config)
+            })
+            .collect()
+    }
+
+    /// Índice da réplica a que o nome de contêiner corresponde
+    pub fn index_of(&self, container_name: &str) -> Option<u32> {
+        let (prefix, suffix) = self.name.split_once(INDEX_PLACEHOLDER)?;
+        let pattern = format!("^{}([0-9]+){}$", regex::escape(prefix), regex::escape(suffix));
+
+        Regex::new(&pattern)
+            .ok()?
+            .captures(container_name)?
+            .get(1)?
+            .as_str()
+            .parse()
+            .ok()
+    }
+}
+
+
+/// Acrescenta às `instances` de cada serviço com template as suas réplicas
+pub fn expand_replicas(mut document: Value) -> anyhow::Result<Value> {
+    let Some(groups) = document.as_mapping_mut() else {
+        return Ok(document);
+    };
+
+    for (group_name, group) in groups.iter_mut() {
+        let Some(group_name) = group_name.as_str() else { continue };
+        if RESERVED_GROUPS.contains(&group_name) {
+            continue;
+        }
+        let Some(group) = group.as_mapping_mut() else { continue };
+
+        for (service_name, service) in group.iter_mut() {
+            let Some(service_name) = service_name.as_str() else { continue };
+            if GROUP_SETTINGS.contains(&service_name) {
+                continue;
+            }
+            let Some(service) = service.as_mapping_mut() else { continue };
+            let Some(template) = ReplicaTemplate::from_service(service_name, service)? else {
+                continue;
+            };
+
+            let instances = service
+                .entry(Value::from("instances"))
+                .or_insert_with(|| Value::Mapping(Mapping::new()));
+            if instances.is_null() {
+                *instances = Value::Mapping(Mapping::new());
+            }
+            let Some(instances) = instances.as_mapping_mut() else {
+                anyhow::bail!("As instances do serviço {service_name} não são um mapping");
+            };
+
+            for (name, config) in template.instances(1..=template.replicas) {
+                if instances.contains_key(name.as_str()) {
+                    anyhow::bail!(
+                        "A réplica {name} do serviço {service_name} tem o nome de uma \
+                         instância declarada"
+                    );
+                }
+                instances.insert(Value::String(name), config);
+            }
+        }
+    }
+
+    Ok(document)
+}
+
+
+/// Substitui o placeholder nos textos do valor. Nos campos de
+/// `NUMERIC_FIELDS`, um valor que vira um número inteiro passa a ser um
+/// número; os demais, como `command` e `tag`, continuam textos.
+fn substitute(value: Value, index: u32) -> Value {
+    match value {
+        Value::String(text) => Value::String(text.replace(INDEX_PLACEHOLDER, &index.to_string())),
+        Value::Sequence(items) => {
+            Value::Sequence(items.into_iter().map(|item| substitute(item, index)).collect())
+        }
+        Value::Mapping(map) => Value::Mapping(
+            map.into_iter()
+                .map(|(key, value)| {
+                    let numeric: bool = key
+                        .as_str()
+                        .is_some_and(|key| NUMERIC_FIELDS.contains(&key))
+                        && value.as_str().is_some_and(|text| text.contains(INDEX_PLACEHOLDER));
+                    let value: Value = match substitute(value, index) {
+                        Value::String(text) if numeric => text
+                            .parse::<u64>()
+                            .map(|number| Value::Number(Number::from(number)))
+                            .unwrap_or(Value::String(text)),
+                        value => value,
+                    };
+                    (substitute(key, index), value)
+                })
+                .collect()
+        ),
+        other => other,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn yaml(text: &str) -> Value {
+        serde_yaml::from_str(text).unwrap()
+    }
+
+    fn template(service: &str) -> ReplicaTemplate {
+        let service: Value = yaml(service);
+        ReplicaTemplate::from_service("api", service.as_mapping().unwrap())
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn substitute_replaces_names_ports_and_environment() {
+        let value = substitute(
+            yaml(
+                "{hostname: 'api-${index}', ports: ['80${index}:80'], \
+                 environment: ['WORKER_ID=${index}'], labels: {'shard-${index}': x}}"
+            ),
+            3,
+        );
+
+        assert_eq!(
+            value,
+            yaml(
+                "{hostname: api-3, ports: ['803:80'], environment: [WORKER_ID=3], \
+                 labels: {shard-3: x}}"
+            )
+        );
+    }
+
+    #[test]
+    fn substitute_converts_only_numeric_fields() {
+        let value = substitute(
+            yaml(
+                "{command: '${index}', tag: '0${index}', \
+                 remotecheck: {port: '80${index}', retries: '${index}', endpoint: '/${index}'}}"
+            ),
+            2,
+        );
+
+        assert_eq!(
+            value,
+            yaml(
+                "{command: '2', tag: '02', \
+                 remotecheck: {port: 802, retries: 2, endpoint: /2}}"
+            )
+        );
+    }
+
+    #[test]
+    fn substitute_keeps_numeric_fields_that_are_not_numbers() {
+        let value = substitute(yaml("{port: '${index}x', status: 200}"), 1);
+        assert_eq!(value, yaml("{port: 1x, status: 200}"));
+    }
+
+    #[test]
+    fn index_of_matches_only_the_name_pattern() {
+        let template = template("{replicas: 2, template: {name: 'web-${index}-a'}}");
+
+        assert_eq!(template.index_of("web-12-a"), Some(12));
+        assert_eq!(template.index_of("web-1-a"), Some(1));
+        assert_eq!(template.index_of("web--a"), None);
+        assert_eq!(template.index_of("web-x-a"), None);
+        assert_eq!(template.index_of("xweb-1-a"), None);
+        assert_eq!(template.index_of("web-1-ab"), None);
+    }
+
+    #[test]
+    fn default_name_uses_the_service_name() {
+        let template = template("{replicas: 2}");
+        assert_eq!(template.instance_name(2), "api2");
+        assert_eq!(template.index_of("api7"), Some(7));
+    }
+
+    #[test]
+    fn expand_replicas_adds_instances_after_the_declared_ones() {
+        let document = expand_replicas(yaml(
+            "
+app:
+  api:
+    image: api:1
+    replicas: 2
+    template: {name: 'api-${index}'}
+    instances: {api-main: null}
+",
+        ))
+        .unwrap();
+
+        let instances: Vec<&str> = document["app"]["api"]["instances"]
+            .as_mapping()
+            .unwrap()
+            .keys()
+            .filter_map(Value::as_str)
+            .collect();
+        assert_eq!(instances, ["api-main", "api-1", "api-2"]);
+    }
+
+    #[test]
+    fn expand_replicas_skips_reserved_groups_and_services_without_template() {
+        let text = "{networks: {api: {replicas: 2}}, app: {db: {image: pg}}}";
+        assert_eq!(expand_replicas(yaml(text)).unwrap(), yaml(text));
+    }
+
+    #[test]
+    fn expand_replicas_rejects_name_collisions() {
+        let err = expand_replicas(yaml(
+            "{app: {api: {replicas: 2, template: {name: 'api-${index}'}, \
+             instances: {api-2: null}}}}"
+        ))
+        .unwrap_err();
+
+        assert!(err.to_string().contains("A réplica api-2 do serviço api"));
+    }
+
+    #[test]
+    fn invalid_templates_are_rejected() {
+        let service: Value = yaml("{replicas: 2, template: {name: api}}");
+        assert!(ReplicaTemplate::from_service("api", service.as_mapping().unwrap()).is_err());
+
+        let service: Value = yaml("{replicas: -1}");
+        assert!(ReplicaTemplate::from_service("api", service.as_mapping().unwrap()).is_err());
+    }
+}
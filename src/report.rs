@@ -0,0 +1,513 @@
+use std::{fs, path::Path, time::Duration};
+
+use serde::Serialize;
+
+use crate::redact::redact;
+
+
+#[derive(Debug)]
+pub enum GroupStatus {
+    Ok,
+    Failed(String),
+    Skipped,
+}
+
+
+#[derive(Debug)]
+pub struct GroupOutcome {
+    pub group_name: String,
+    pub status: GroupStatus,
+    pub duration: Duration,
+}
+
+
+/// O que aconteceu com o contêiner da instância
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InstanceAction {
+    /// Não havia contêiner com o nome da instância
+    Created,
+    /// Havia um contêiner com o nome da instância e ele foi recriado;
+    /// `config_changed` diz se a imagem ou as opções mudaram
+    Recreated,
+    /// Havia um contêiner rodando com a mesma imagem e as mesmas opções, e
+    /// ele foi mantido
+    Unchanged,
+    Failed,
+    /// Um smoke test falhou e o contêiner anterior voltou a rodar
+    #[serde(rename = "rolled_back")]
+    RolledBack,
+}
+
+
+impl InstanceAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            InstanceAction::Created => "created",
+            InstanceAction::Recreated => "recreated",
+            InstanceAction::Unchanged => "unchanged",
+            InstanceAction::Failed => "failed",
+            InstanceAction::RolledBack => "rolled_back",
+        }
+    }
+}
+
+
+/// Resultado do `remotecheck` da instância
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthOutcome {
+    Passed,
+    Failed,
+    /// Sem `remotecheck` ou o deploy falhou antes da verificação
+    Skipped,
+}
+
+
+impl HealthOutcome {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HealthOutcome::Passed => "passed",
+            HealthOutcome::Failed => "failed",
+            HealthOutcome::Skipped => "skipped",
+        }
+    }
+}
+
+
+/// Envio de um arquivo para o host
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Transfer {
+    pub bytes: u64,
+    pub seconds: f64,
+}
+
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InstanceReport {
+    pub group: String,
+    pub service: String,
+    pub instance: String,
+    pub host: String,
+    pub action: InstanceAction,
+    pub image: String,
+    pub image_id: Option<String>,
+    /// Se a imagem ou as opções do contêiner recriado mudaram em relação ao
+    /// anterior. Só aparece no relatório para instâncias recriadas.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub config_changed: Option<bool>,
+    /// Envio da imagem para o host, compartilhado pelas instâncias do host
+    pub transfer: Option<Transfer>,
+    pub healthcheck: HealthOutcome,
+    pub seconds: f64,
+    pub error: Option<String>,
+}
+
+
+impl InstanceReport {
+    pub fn new(group: &str, service: &str, instance: &str, host: &str, image: &str) -> Self {
+        InstanceReport {
+            group: group.to_owned(),
+            service: service.to_owned(),
+            instance: instance.to_owned(),
+            host: host.to_owned(),
+            action: InstanceAction::Failed,
+            image: image.to_owned(),
+            image_id: None,
+            config_changed: None,
+            transfer: None,
+            healthcheck: HealthOutcome::Skipped,
+            seconds: 0.0,
+            error: None,
+        }
+    }
+
+    /// Marca a instância como falha, com a mensagem já mascarada
+    pub fn fail(&mut self, err: &anyhow::Error) {
+        self.action = InstanceAction::Failed;
+        self.error = Some(redact(&format!("{err:#}")));
+    }
+}
+
+
+/// Resultado de um smoke test
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmokeOutcome {
+    Passed,
+    Failed,
+    /// Falhou, mas com `on_failure: warn`
+    Warned,
+}
+
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SmokeTestReport {
+    pub group: String,
+    pub name: String,
+    pub status: SmokeOutcome,
+    pub seconds: f64,
+    pub error: Option<String>,
+}
+
+
+/// Tudo o que o deploy registrou para o relatório
+#[derive(Debug, Default)]
+pub struct DeployRecords {
+    pub instances: Vec<InstanceReport>,
+    pub smoke_tests: Vec<SmokeTestReport>,
+}
+
+
+#[derive(Debug, Serialize)]
+struct GroupReport {
+    name: String,
+    status: &'static str,
+    seconds: f64,
+    error: Option<String>,
+}
+
+
+#[derive(Debug, Serialize)]
+struct DeployReport<'a> {
+    success: bool,
+    dry_run: bool,
+    seconds: f64,
+    groups: Vec<GroupReport>,
+    instances: &'a [InstanceReport],
+    smoke_tests: &'a [SmokeTestReport],
+}
+
+
+/// Grava o relatório do deploy em cada caminho: JUnit XML para arquivos
+/// `.xml` e JSON para os demais
+pub fn write_reports(
+    paths: &[String],
+    groups: &[GroupOutcome],
+    records: &DeployRecords,
+    duration: Duration,
+    dry_run: bool,
+) -> anyhow::Result<()> {
+
+    for path in paths {
+        let is_xml = Path::new(path)
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("xml"));
+
+        let content: String = if is_xml {
+            junit(groups, records, duration)
+        } else {
+            let report = DeployReport {
+                success: groups.iter().all(|group| !matches!(group.status, GroupStatus::Failed(_))),
+                dry_run,
+                seconds: duration.as_secs_f64(),
+                groups: groups.iter().map(group_report).collect(),
+                instances: &records.instances,
+                smoke_tests: &records.smoke_tests,
+            };
+            serde_json::to_string_pretty(&report)? + "\n"
+        };
+
+        fs::write(path, content)
+            .map_err(|err| anyhow::anyhow!("Não foi possível gravar o relatório {path}: {err}"))?;
+    }
+
+    Ok(())
+}
+
+
+fn group_report(outcome: &GroupOutcome) -> GroupReport {
+    let (status, error) = match &outcome.status {
+        GroupStatus::Ok => ("ok", None),
+        GroupStatus::Failed(err) => ("failed", Some(redact(err))),
+        GroupStatus::Skipped => ("skipped", None),
+    };
+
+    GroupReport {
+        name: outcome.group_name.clone(),
+        status,
+        seconds: outcome.duration.as_secs_f64(),
+        error,
+    }
+}
+
+
+/// Relatório no formato JUnit: um `testsuite` por grupo e um `testcase` por
+/// instância e host e por smoke test. Grupos sem instâncias (networks,
+/// volumes, ignorados) e falhas do grupo fora das instâncias e dos smoke
+/// tests viram um `testcase` do próprio grupo.
+fn junit(groups: &[GroupOutcome], records: &DeployRecords, duration: Duration) -> String {
+    let mut suites: String = String::new();
+    let mut total_tests: usize = 0;
+    let mut total_failures: usize = 0;
+
+    for outcome in groups {
+        let group = group_report(outcome);
+        let mut cases: String = String::new();
+        let mut tests: usize = 0;
+        let mut failures: usize = 0;
+        let mut skipped: usize = 0;
+
+        let group_instances: Vec<&InstanceReport> = records.instances
+            .iter()
+            .filter(|instance| instance.group == group.name)
+            .collect();
+
+        for instance in &group_instances {
+            tests += 1;
+            cases += &format!(
+                "    <testcase classname=\"{}.{}\" name=\"{}@{}\" time=\"{:.3}\">\n",
+                escape_xml(&instance.group),
+                escape_xml(&instance.service),
+                escape_xml(&instance.instance),
+                escape_xml(&instance.host),
+                instance.seconds
+            );
+            if let Some(ref error) = instance.error {
+                failures += 1;
+                cases += &format!("      <failure message=\"{}\"/>\n", escape_xml(error));
+            }
+            cases += &format!(
+                "      <system-out>{}</system-out>\n    </testcase>\n",
+                escape_xml(&instance_details(instance))
+            );
+        }
+
+        let group_smoke_tests: Vec<&SmokeTestReport> = records.smoke_tests
+            .iter()
+            .filter(|test| test.group == group.name)
+            .collect();
+
+        for test in &group_smoke_tests {
+            tests += 1;
+            cases += &format!(
+                "    <testcase classname=\"{}.smoke_tests\" name=\"{}\" time=\"{:.3}\"",
+                escape_xml(&test.group),
+                escape_xml(&test.name),
+                test.seconds
+            );
+            match (test.status, &test.error) {
+                (SmokeOutcome::Failed, Some(error)) => {
+                    failures += 1;
+                    cases += &format!(
+                        ">\n      <failure message=\"{}\"/>\n    </testcase>\n",
+                        escape_xml(error)
+                    );
+                }
+                (SmokeOutcome::Warned, Some(error)) => {
+                    cases += &format!(
+                        ">\n      <system-err>{}</system-err>\n    </testcase>\n",
+                        escape_xml(error)
+                    );
+                }
+                _ => cases += "/>\n",
+            }
+        }
+
+        let failed_outside_instances: bool = group.status == "failed"
+            && !group_instances.iter().any(|instance| instance.error.is_some())
+            && group_smoke_tests.iter().all(|test| test.status != SmokeOutcome::Failed);
+
+        if (group_instances.is_empty() && group_smoke_tests.is_empty())
+            || failed_outside_instances {
+            tests += 1;
+            cases += &format!(
+                "    <testcase classname=\"{name}\" name=\"{name}\" time=\"{:.3}\"",
+                group.seconds,
+                name = escape_xml(&group.name)
+            );
+            match (group.status, &group.error) {
+                ("failed", Some(error)) => {
+                    failures += 1;
+                    cases += &format!(
+                        ">\n      <failure message=\"{}\"/>\n    </testcase>\n",
+                        escape_xml(error)
+                    );
+                }
+                ("skipped", _) => {
+                    skipped += 1;
+                    cases += ">\n      <skipped/>\n    </testcase>\n";
+                }
+                _ => cases += "/>\n",
+            }
+        }
+
+        total_tests += tests;
+        total_failures += failures;
+        suites += &format!(
+            "  <testsuite name=\"{}\" tests=\"{tests}\" failures=\"{failures}\" \
+             skipped=\"{skipped}\" time=\"{:.3}\">\n{cases}  </testsuite>\n",
+            escape_xml(&group.name),
+            group.seconds
+        );
+    }
+
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <testsuites name=\"ddr deploy\" tests=\"{total_tests}\" failures=\"{total_failures}\" \
+         time=\"{:.3}\">\n{suites}</testsuites>\n",
+        duration.as_secs_f64()
+    )
+}
+
+
+/// Detalhes da instância no `system-out` do JUnit
+fn instance_details(instance: &InstanceReport) -> String {
+    let mut details: Vec<String> = vec![
+        format!("action: {}", instance.action.as_str()),
+        format!("image: {}", instance.image),
+    ];
+    if let Some(config_changed) = instance.config_changed {
+        details.push(format!("config_changed: {config_changed}"));
+    }
+    if let Some(ref image_id) = instance.image_id {
+        details.push(format!("image_id: {image_id}"));
+    }
+    if let Some(transfer) = instance.transfer {
+        details.push(format!("transfer: {} bytes em {:.1}s", transfer.bytes, transfer.seconds));
+    }
+    details.push(format!("healthcheck: {}", instance.healthcheck.as_str()));
+
+    details.join("\n")
+}
+
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn outcome(group_name: &str, status: GroupStatus) -> GroupOutcome {
+        GroupOutcome {
+            group_name: group_name.to_owned(),
+            status,
+            duration: Duration::from_millis(1500),
+        }
+    }
+
+    fn instance(group: &str, instance: &str, error: Option<&str>) -> InstanceReport {
+        let mut report = InstanceReport::new(group, "api", instance, "web1", "app:1");
+        report.action = InstanceAction::Recreated;
+        report.config_changed = Some(true);
+        report.healthcheck = HealthOutcome::Passed;
+        report.error = error.map(str::to_owned);
+        report
+    }
+
+    #[test]
+    fn escape_xml_escapes_markup_characters() {
+        assert_eq!(
+            escape_xml(r#"<a href="x">'b' & c</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;&apos;b&apos; &amp; c&lt;/a&gt;"
+        );
+        assert_eq!(escape_xml("&amp;"), "&amp;amp;");
+        assert_eq!(escape_xml("sem nada"), "sem nada");
+    }
+
+    #[test]
+    fn junit_has_a_suite_per_group_and_a_case_per_instance() {
+        let groups = [outcome("app", GroupStatus::Failed("falhou".to_owned()))];
+        let records = DeployRecords {
+            instances: vec![
+                instance("app", "api_1", None),
+                instance("app", "api_2", Some("porta <80> ocupada")),
+            ],
+            smoke_tests: Vec::new(),
+        };
+
+        let xml = junit(&groups, &records, Duration::from_secs(2));
+
+        assert!(xml.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n"));
+        assert!(xml.contains(
+            "<testsuites name=\"ddr deploy\" tests=\"2\" failures=\"1\" time=\"2.000\">"
+        ));
+        assert!(xml.contains(
+            "<testsuite name=\"app\" tests=\"2\" failures=\"1\" skipped=\"0\" time=\"1.500\">"
+        ));
+        assert!(xml.contains("<testcase classname=\"app.api\" name=\"api_1@web1\""));
+        assert!(xml.contains("<failure message=\"porta &lt;80&gt; ocupada\"/>"));
+        assert!(xml.contains("action: recreated\nimage: app:1\nconfig_changed: true"));
+        // A falha do grupo já aparece na instância, sem testcase próprio
+        assert!(!xml.contains("classname=\"app\" name=\"app\""));
+    }
+
+    #[test]
+    fn junit_reports_groups_without_instances_and_smoke_tests() {
+        let groups = [
+            outcome("networks", GroupStatus::Ok),
+            outcome("jobs", GroupStatus::Skipped),
+            outcome("app", GroupStatus::Ok),
+        ];
+        let smoke_test = |name: &str, status, error: Option<&str>| SmokeTestReport {
+            group: "app".to_owned(),
+            name: name.to_owned(),
+            status,
+            seconds: 0.25,
+            error: error.map(str::to_owned),
+        };
+        let records = DeployRecords {
+            instances: vec![instance("app", "api_1", None)],
+            smoke_tests: vec![
+                smoke_test("ping", SmokeOutcome::Passed, None),
+                smoke_test("login", SmokeOutcome::Failed, Some("401")),
+                smoke_test("lento", SmokeOutcome::Warned, Some("demorou")),
+            ],
+        };
+
+        let xml = junit(&groups, &records, Duration::from_secs(3));
+
+        assert!(xml.contains(
+            "<testcase classname=\"networks\" name=\"networks\" time=\"1.500\"/>"
+        ));
+        assert!(xml.contains(
+            "<testsuite name=\"jobs\" tests=\"1\" failures=\"0\" skipped=\"1\" time=\"1.500\">"
+        ));
+        assert!(xml.contains(
+            "<testsuite name=\"app\" tests=\"4\" failures=\"1\" skipped=\"0\" time=\"1.500\">"
+        ));
+        assert!(xml.contains(
+            "<testcase classname=\"app.smoke_tests\" name=\"ping\" time=\"0.250\"/>"
+        ));
+        assert!(xml.contains("<failure message=\"401\"/>"));
+        assert!(xml.contains("<system-err>demorou</system-err>"));
+        assert!(xml.contains("tests=\"6\" failures=\"1\""));
+    }
+
+    #[test]
+    fn junit_reports_group_failures_outside_instances() {
+        let groups = [outcome("app", GroupStatus::Failed("hook <pre> falhou".to_owned()))];
+        let records = DeployRecords {
+            instances: vec![instance("app", "api_1", None)],
+            smoke_tests: Vec::new(),
+        };
+
+        let xml = junit(&groups, &records, Duration::from_secs(1));
+
+        assert!(xml.contains("<testcase classname=\"app\" name=\"app\" time=\"1.500\">"));
+        assert!(xml.contains("<failure message=\"hook &lt;pre&gt; falhou\"/>"));
+        assert!(xml.contains("tests=\"2\" failures=\"1\""));
+    }
+
+    #[test]
+    fn config_changed_is_reported_only_for_recreated_instances() {
+        let mut unchanged = instance("app", "api_1", None);
+        unchanged.action = InstanceAction::Unchanged;
+        unchanged.config_changed = None;
+
+        let json = serde_json::to_value(&unchanged).unwrap();
+        assert_eq!(json["action"], "unchanged");
+        assert!(json.get("config_changed").is_none());
+
+        let json = serde_json::to_value(instance("app", "api_2", None)).unwrap();
+        assert_eq!(json["action"], "recreated");
+        assert_eq!(json["config_changed"], true);
+    }
+}
@@ -0,0 +1,241 @@
+use std::{collections::BTreeSet, ops::RangeInclusive};
+
+use serde_yaml::{Mapping, Value};
+use tracing::info;
+
+use crate::{
+    hosts::{Inventory, Sessions},
+    instances::{declared_instances, group_services, RESERVED_GROUPS},
+    lifecycle::{handle_lifecycle, DownOptions, LifecycleAction},
+    models::{DeployFilter, InstanceSelector},
+    replicas::ReplicaTemplate,
+    report::DeployRecords,
+    services::handle_group,
+    utils::run_remote_output,
+};
+
+
+/// Ajusta o número de réplicas dos serviços com template (`api=6`): cria as
+/// que faltam nos hosts e remove as de índice maior, sem mexer nas demais.
+/// O `replicas:` do arquivo de deploy não é alterado.
+pub fn handle_scale(
+    inventory: &Inventory,
+    deploy_map: &Mapping,
+    targets: &[String],
+    dry_run: bool,
+) -> anyhow::Result<()> {
+
+    // Todos os alvos são validados antes de qualquer mudança
+    let mut scales: Vec<(String, ReplicaTemplate, u32)> = Vec::new();
+    for target in targets {
+        let (service_name, replicas) = target
+            .split_once('=')
+            .and_then(|(service, replicas)| Some((service, replicas.parse::<u32>().ok()?)))
+            .ok_or_else(|| anyhow::anyhow!("Escala inválida: {target} (use SERVIÇO=N)"))?;
+
+        let (group_name, template) = find_template(deploy_map, service_name)?;
+        scales.push((group_name, template, replicas));
+    }
+
+    let mut sessions: Sessions = Sessions::new(inventory);
+
+    for (group_name, template, replicas) in scales {
+        let service_name: &str = &template.service_name;
+
+        // Réplicas que já existem nos hosts do serviço, pelo nome do contêiner
+        let declared: Mapping = with_replicas(
+            deploy_map,
+            &group_name,
+            &template,
+            1..=replicas.max(template.replicas),
+        )?;
+        let mut existing: BTreeSet<u32> = BTreeSet::new();
+        for host in service_hosts(inventory, &declared, &group_name, service_name)? {
+            let names: String = run_remote_output(
+                sessions.get(&host)?,
+                "docker ps -a --format '{{.Names}}'"
+            )?;
+            existing.extend(names.lines().filter_map(|name| template.index_of(name.trim())));
+        }
+
+        let missing: Vec<u32> = (1..=replicas).filter(|index| !existing.contains(index)).collect();
+        let extra: Vec<u32> = existing.iter().copied().filter(|&index| index > replicas).collect();
+
+        info!(
+            "Serviço {service_name}: {replicas} réplicas ({} a criar, {} a remover)",
+            missing.len(),
+            extra.len()
+        );
+
+        if !missing.is_empty() {
+            let scaled: Mapping = with_replicas(deploy_map, &group_name, &template, 1..=replicas)?;
+            let filter = DeployFilter {
+                services: vec![service_name.to_owned()],
+                instances: missing.iter().map(|&index| template.instance_name(index)).collect(),
+                with_deps: false,
+            };
+
+            handle_group(
+                &mut sessions,
+                &group_name,
+                &scaled,
+                &filter,
+                &mut DeployRecords::default(),
+                dry_run
+            )?;
+        }
+
+        if let Some(&last) = extra.last() {
+            // As réplicas removidas precisam estar declaradas para o `down`
+            let scaled: Mapping = with_replicas(deploy_map, &group_name, &template, 1..=last)?;
+            let selector = InstanceSelector {
+                group_name: Some(group_name.clone()),
+                services: vec![service_name.to_owned()],
+                instances: extra.iter().map(|&index| template.instance_name(index)).collect(),
+            };
+
+            handle_lifecycle(
+                inventory,
+                &scaled,
+                &selector,
+                LifecycleAction::Down,
+                &DownOptions { yes: true, ..DownOptions::default() },
+                dry_run
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+
+/// Grupo e template do serviço
+fn find_template(
+    deploy_map: &Mapping,
+    service_name: &str,
+) -> anyhow::Result<(String, ReplicaTemplate)> {
+
+    let mut found: Option<(String, ReplicaTemplate)> = None;
+    for (group_name, group) in deploy_map {
+        let Some(group_name) = group_name.as_str() else { continue };
+        if RESERVED_GROUPS.contains(&group_name) {
+            continue;
+        }
+        let Some(group) = group.as_mapping() else { continue };
+        let Some(service) = group_services(group)
+            .get(service_name)
+            .and_then(Value::as_mapping)
+            .cloned() else {
+            continue;
+        };
+
+        if found.is_some() {
+            anyhow::bail!("O serviço {service_name} está em mais de um grupo");
+        }
+        let template = ReplicaTemplate::from_service(service_name, &service)?.ok_or_else(|| {
+            anyhow::anyhow!("O serviço {service_name} não tem template de réplicas")
+        })?;
+        found = Some((group_name.to_owned(), template));
+    }
+
+    found.ok_or_else(|| anyhow::anyhow!("Serviço não encontrado: {service_name}"))
+}
+
+
+/// Cópia do arquivo de deploy em que o serviço tem as réplicas informadas
+/// no lugar das declaradas em `replicas:`
+fn with_replicas(
+    deploy_map: &Mapping,
+    group_name: &str,
+    template: &ReplicaTemplate,
+    indexes: RangeInclusive<u32>,
+) -> anyhow::Result<Mapping> {
+
+    let mut scaled: Mapping = deploy_map.clone();
+    let instances: &mut Mapping = scaled
+        .get_mut(group_name)
+        .and_then(|group| group.get_mut(template.service_name.as_str()))
+        .and_then(|service| service.get_mut("instances"))
+        .and_then(Value::as_mapping_mut)
+        .ok_or_else(|| {
+            anyhow::anyhow!("O serviço {} não tem instances", template.service_name)
+        })?;
+
+    for (name, _) in template.instances(1..=template.replicas) {
+        instances.remove(name.as_str());
+    }
+    for (name, config) in template.instances(indexes) {
+        instances.insert(Value::String(name), config);
+    }
+
+    Ok(scaled)
+}
+
+
+/// Hosts em que as instâncias do serviço rodam, sem repetição
+fn service_hosts(
+    inventory: &Inventory,
+    deploy_map: &Mapping,
+    group_name: &str,
+    service_name: &str,
+) -> anyhow::Result<BTreeSet<String>> {
+
+    let mut hosts: BTreeSet<String> = BTreeSet::new();
+    for instance in declared_instances(deploy_map, Some(group_name))? {
+        if instance.service_name == service_name {
+            hosts.extend(inventory.hosts_for(&instance)?);
+        }
+    }
+
+    Ok(hosts)
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replicas::expand_replicas;
+
+    const DEPLOY: &str = "
+hosts:
+  web1: {address: 10.0.0.1, user: u, dir: /d, labels: {role: web}}
+  web2: {address: 10.0.0.2, user: u, dir: /d, labels: {role: web}}
+  db1: {address: 10.0.0.3, user: u, dir: /d, labels: {role: db}}
+app:
+  api:
+    replicas: 2
+    template:
+      placement: {labels: {role: web}}
+  db:
+    instances:
+      db: {host: db1}
+";
+
+    fn deploy_map() -> Mapping {
+        let document: Value = serde_yaml::from_str(DEPLOY).unwrap();
+        expand_replicas(document).unwrap().as_mapping().unwrap().clone()
+    }
+
+    #[test]
+    fn only_the_hosts_of_the_service_are_probed() {
+        let deploy_map = deploy_map();
+        let inventory = Inventory::load(&deploy_map, Err(anyhow::anyhow!("sem SSH_HOST"))).unwrap();
+
+        let (group_name, template) = find_template(&deploy_map, "api").unwrap();
+        let scaled = with_replicas(&deploy_map, &group_name, &template, 1..=4).unwrap();
+        let hosts = service_hosts(&inventory, &scaled, &group_name, "api").unwrap();
+
+        assert_eq!(hosts, BTreeSet::from(["web1".to_owned(), "web2".to_owned()]));
+    }
+
+    #[test]
+    fn a_service_without_instances_is_an_error() {
+        let mut deploy_map = deploy_map();
+        let (group_name, template) = find_template(&deploy_map, "api").unwrap();
+        deploy_map["app"]["api"].as_mapping_mut().unwrap().remove("instances");
+
+        let err = with_replicas(&deploy_map, &group_name, &template, 1..=3).unwrap_err();
+        assert_eq!(err.to_string(), "O serviço api não tem instances");
+    }
+}
@@ -0,0 +1,421 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    env,
+    fs::{self, OpenOptions},
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use age::{
+    scrypt,
+    secrecy::SecretString,
+    x25519,
+};
+
+use shell_escape::escape;
+use tracing::debug;
+
+use crate::{
+    backend::Backend,
+    models::SecretsCommand,
+    redact::register_secret,
+    utils::{run_remote, scp_write},
+};
+
+
+/// Diretório em tmpfs onde ficam os segredos das instâncias no host
+const INSTANCE_SECRETS_DIR: &str = "/dev/shm/ddr-secrets";
+
+/// Dono padrão dos arquivos de segredos no host
+const DEFAULT_SECRETS_OWNER: &str = "0:0";
+
+
+/// Chave usada para criptografar e descriptografar o arquivo de segredos
+enum SecretsKey {
+    Identity(x25519::Identity),
+    Passphrase(SecretString),
+}
+
+
+impl SecretsKey {
+    /// Usa o arquivo de identidade (--secrets-key ou DDR_SECRETS_KEY) se
+    /// houver; senão a senha de DDR_SECRETS_PASSPHRASE ou digitada. Com
+    /// `confirm`, a senha digitada é pedida duas vezes.
+    fn resolve(key_file: Option<&str>, confirm: bool) -> anyhow::Result<Self> {
+        let key_file: Option<String> = key_file
+            .map(str::to_owned)
+            .or_else(|| env::var("DDR_SECRETS_KEY").ok());
+
+        if let Some(key_file) = key_file {
+            let content = fs::read_to_string(&key_file)
+                .map_err(|err| anyhow::anyhow!("Não foi possível ler {key_file}: {err}"))?;
+
+            let identity = content
+                .lines()
+                .map(str::trim)
+                .find(|line| line.starts_with("AGE-SECRET-KEY-"))
+                .ok_or_else(|| anyhow::anyhow!("Nenhuma identidade age em {key_file}"))?
+                .parse::<x25519::Identity>()
+                .map_err(|err| anyhow::anyhow!("Identidade age inválida em {key_file}: {err}"))?;
+
+            return Ok(SecretsKey::Identity(identity));
+        }
+
+        if let Ok(passphrase) = env::var("DDR_SECRETS_PASSPHRASE") {
+            return Ok(SecretsKey::Passphrase(passphrase.into()));
+        }
+
+        let passphrase = rpassword::prompt_password("Senha do arquivo de segredos: ")?;
+        if confirm && rpassword::prompt_password("Confirme a senha: ")? != passphrase {
+            anyhow::bail!("As senhas não conferem");
+        }
+
+        Ok(SecretsKey::Passphrase(passphrase.into()))
+    }
+
+    /// Criptografa em formato age com armadura ASCII
+    fn encrypt(&self, plaintext: &[u8]) -> anyhow::Result<String> {
+        let ciphertext = match self {
+            SecretsKey::Identity(identity) => {
+                age::encrypt_and_armor(&identity.to_public(), plaintext)?
+            }
+            SecretsKey::Passphrase(passphrase) => {
+                age::encrypt_and_armor(&scrypt::Recipient::new(passphrase.clone()), plaintext)?
+            }
+        };
+
+        Ok(ciphertext)
+    }
+
+    fn decrypt(&self, ciphertext: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let plaintext = match self {
+            SecretsKey::Identity(identity) => age::decrypt(identity, ciphertext),
+            SecretsKey::Passphrase(passphrase) => {
+                age::decrypt(&scrypt::Identity::new(passphrase.clone()), ciphertext)
+            }
+        };
+
+        plaintext.map_err(|err| {
+            anyhow::anyhow!("Não foi possível descriptografar os segredos: {err}")
+        })
+    }
+}
+
+
+/// Descriptografa o arquivo de segredos em memória e devolve suas variáveis.
+/// Se o arquivo não existir, não há segredos.
+pub fn load_secrets(
+    file_path: &str,
+    key_file: Option<&str>,
+) -> anyhow::Result<HashMap<String, String>> {
+
+    if !Path::new(file_path).exists() {
+        return Ok(HashMap::new());
+    }
+
+    let ciphertext = fs::read(file_path)?;
+    let plaintext = SecretsKey::resolve(key_file, false)?.decrypt(&ciphertext)?;
+
+    parse_env(&plaintext).map_err(|err| anyhow::anyhow!("{file_path}: {err}"))
+}
+
+
+pub fn handle_secrets(
+    action: &SecretsCommand,
+    secrets_file: &str,
+    key_file: Option<&str>,
+) -> anyhow::Result<()> {
+
+    match action {
+        SecretsCommand::Encrypt { input, output } => {
+            let output = output.clone().unwrap_or_else(|| format!("{input}.age"));
+            let plaintext = fs::read(input)?;
+            parse_env(&plaintext).map_err(|err| anyhow::anyhow!("{input}: {err}"))?;
+
+            let key = SecretsKey::resolve(key_file, true)?;
+            write_atomically(Path::new(&output), key.encrypt(&plaintext)?.as_bytes())?;
+
+            println!("Segredos criptografados em {output}");
+            println!("Apague o arquivo {input} se não for mais usá-lo");
+        }
+        SecretsCommand::Decrypt { file, output } => {
+            let file = file.as_deref().unwrap_or(secrets_file);
+            let ciphertext = fs::read(file)
+                .map_err(|err| anyhow::anyhow!("Não foi possível ler {file}: {err}"))?;
+            let plaintext = SecretsKey::resolve(key_file, false)?.decrypt(&ciphertext)?;
+
+            match output {
+                Some(output) => write_private(Path::new(output), &plaintext)?,
+                None => std::io::stdout().write_all(&plaintext)?,
+            }
+        }
+        SecretsCommand::Edit { file } => {
+            let file = Path::new(file.as_deref().unwrap_or(secrets_file));
+            edit(file, key_file)?;
+        }
+    }
+
+    Ok(())
+}
+
+
+/// Descriptografa para um arquivo temporário só do usuário, abre o editor e
+/// criptografa o resultado de volta. O arquivo temporário é sempre apagado.
+fn edit(file: &Path, key_file: Option<&str>) -> anyhow::Result<()> {
+    let exists = file.exists();
+    let key = SecretsKey::resolve(key_file, !exists)?;
+
+    let plaintext: Vec<u8> = if exists { key.decrypt(&fs::read(file)?)? } else { Vec::new() };
+
+    let temp_file: PathBuf = env::temp_dir()
+        .join(format!("ddr-secrets-{}.env", std::process::id()));
+    write_private(&temp_file, &plaintext)?;
+
+    let edited = run_editor(&temp_file).and_then(|_| Ok(fs::read(&temp_file)?));
+
+    // Sobrescreve antes de apagar para não deixar o texto puro no disco
+    let length = fs::metadata(&temp_file).map(|metadata| metadata.len()).unwrap_or(0);
+    fs::write(&temp_file, vec![0u8; length as usize]).ok();
+    fs::remove_file(&temp_file).ok();
+
+    let edited = edited?;
+    if exists && edited == plaintext {
+        println!("Nenhuma alteração em {}", file.display());
+        return Ok(());
+    }
+
+    parse_env(&edited)?;
+    write_atomically(file, key.encrypt(&edited)?.as_bytes())?;
+    println!("Segredos salvos em {}", file.display());
+
+    Ok(())
+}
+
+
+fn run_editor(file: &Path) -> anyhow::Result<()> {
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_owned());
+
+    // O editor pode ter argumentos (ex: "code --wait")
+    let mut parts = editor.split_whitespace();
+    let program = parts.next().ok_or_else(|| anyhow::anyhow!("Editor não definido"))?;
+
+    let status = Command::new(program).args(parts).arg(file).status()?;
+    if !status.success() {
+        anyhow::bail!("O editor terminou com erro ({status}); os segredos não foram alterados");
+    }
+
+    Ok(())
+}
+
+
+/// Lê o conteúdo no formato .env
+fn parse_env(content: &[u8]) -> anyhow::Result<HashMap<String, String>> {
+    let mut variables = HashMap::new();
+    for item in dotenvy::from_read_iter(content) {
+        let (key, value) = item.map_err(|err| anyhow::anyhow!("conteúdo .env inválido: {err}"))?;
+        variables.insert(key, value);
+    }
+
+    Ok(variables)
+}
+
+
+/// Cria o arquivo com permissão 0600
+fn write_private(path: &Path, content: &[u8]) -> anyhow::Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    file.write_all(content)?;
+
+    Ok(())
+}
+
+
+/// Grava em um arquivo temporário ao lado e renomeia, para nunca deixar o
+/// arquivo de segredos pela metade
+fn write_atomically(path: &Path, content: &[u8]) -> anyhow::Result<()> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+
+    write_private(&temp_path, content)?;
+    fs::rename(&temp_path, path)?;
+
+    Ok(())
+}
+
+
+/// Diretório dos segredos de uma instância no host
+pub fn instance_secrets_dir(instance_name: &str) -> String {
+    format!("{INSTANCE_SECRETS_DIR}/{instance_name}")
+}
+
+
+/// Bind mount dos segredos da instância em /run/secrets
+pub fn instance_secrets_bind(instance_name: &str) -> String {
+    format!("{}:/run/secrets:ro", instance_secrets_dir(instance_name))
+}
+
+
+/// Opção do `docker run` que monta os segredos da instância em /run/secrets
+pub fn instance_secrets_mount(instance_name: &str) -> String {
+    format!("-v {}", instance_secrets_bind(instance_name))
+}
+
+
+/// Grava os segredos da instância em tmpfs no host, um arquivo por segredo.
+/// Os valores são enviados por scp, sem passar por nenhum comando, para um
+/// diretório só do usuário SSH; depois os arquivos passam a ser só de
+/// leitura do `owner` (padrão root), com sudo se o usuário SSH não puder
+/// fazer o chown.
+pub fn write_instance_secrets(
+    session: &dyn Backend,
+    instance_name: &str,
+    secrets: &BTreeMap<String, String>,
+    owner: Option<&str>,
+) -> anyhow::Result<()> {
+
+    for name in secrets.keys() {
+        if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+            anyhow::bail!("Nome de segredo inválido na instância {instance_name}: {name:?}");
+        }
+    }
+
+    let owner: &str = owner.unwrap_or(DEFAULT_SECRETS_OWNER);
+    let valid_owner = owner
+        .split(':')
+        .all(|id| !id.is_empty() && id.chars().all(|c| c.is_ascii_digit()));
+    if !valid_owner || owner.matches(':').count() > 1 {
+        anyhow::bail!(
+            "secrets_owner inválido na instância {instance_name}: {owner} (use UID[:GID])"
+        );
+    }
+
+    let directory = instance_secrets_dir(instance_name);
+    let dir = escape(directory.as_str().into());
+    run_remote(
+        session,
+        &format!(
+            "{} && umask 077 && mkdir -p {dir}",
+            remove_instance_secrets(instance_name)
+        ),
+    )?;
+
+    for (name, value) in secrets {
+        register_secret(value);
+        scp_write(session, &format!("{directory}/{name}"), value.as_bytes(), 0o400)?;
+        debug!("Segredo {name} gravado para a instância {instance_name}");
+    }
+
+    run_remote(
+        session,
+        &privileged(&format!("chown -R {owner} {dir} && chmod 0500 {dir}")),
+    )?;
+
+    Ok(())
+}
+
+
+/// Comando que apaga os segredos da instância no host, que podem ser de
+/// outro usuário
+pub fn remove_instance_secrets(instance_name: &str) -> String {
+    privileged(&format!("rm -rf {}", escape(instance_secrets_dir(instance_name).into())))
+}
+
+
+/// Executa o comando como o usuário SSH e, se falhar, com `sudo -n`
+fn privileged(command: &str) -> String {
+    format!("{{ {command}; }} 2>/dev/null || sudo -n sh -c {}", escape(command.into()))
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use age::secrecy::ExposeSecret;
+
+    use super::*;
+
+    /// Grava uma identidade nova num arquivo temporário e devolve o caminho
+    fn key_file(name: &str) -> (x25519::Identity, PathBuf) {
+        let identity = x25519::Identity::generate();
+        let path = env::temp_dir().join(format!("ddr-test-{}-{name}.key", std::process::id()));
+        write_private(&path, identity.to_string().expose_secret().as_bytes()).unwrap();
+        (identity, path)
+    }
+
+    #[test]
+    fn encrypted_secrets_round_trip() {
+        let plaintext = b"TOKEN=round-trip-token\n";
+
+        let identity = SecretsKey::Identity(x25519::Identity::generate());
+        let ciphertext = identity.encrypt(plaintext).unwrap();
+        assert!(ciphertext.starts_with("-----BEGIN AGE ENCRYPTED FILE-----"));
+        assert_eq!(identity.decrypt(ciphertext.as_bytes()).unwrap(), plaintext);
+
+        let passphrase = SecretsKey::Passphrase("senha de teste".to_owned().into());
+        let ciphertext = passphrase.encrypt(plaintext).unwrap();
+        assert_eq!(passphrase.decrypt(ciphertext.as_bytes()).unwrap(), plaintext);
+    }
+
+    #[test]
+    fn secrets_are_loaded_with_the_right_key_only() {
+        let (identity, right_key) = key_file("right");
+        let (_, wrong_key) = key_file("wrong");
+        let secrets_file = env::temp_dir()
+            .join(format!("ddr-test-{}-secrets.env.age", std::process::id()));
+
+        let ciphertext = SecretsKey::Identity(identity)
+            .encrypt(b"DB_PASSWORD=load-secrets-password\n")
+            .unwrap();
+        write_private(&secrets_file, ciphertext.as_bytes()).unwrap();
+
+        let file = secrets_file.to_str().unwrap();
+        let loaded = load_secrets(file, right_key.to_str());
+        let wrong = load_secrets(file, wrong_key.to_str());
+
+        for path in [&right_key, &wrong_key, &secrets_file] {
+            fs::remove_file(path).ok();
+        }
+
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded["DB_PASSWORD"], "load-secrets-password");
+
+        let err = wrong.unwrap_err();
+        assert!(format!("{err:#}").contains("Não foi possível descriptografar os segredos"));
+    }
+
+    #[test]
+    fn a_missing_secrets_file_has_no_secrets() {
+        let missing = env::temp_dir()
+            .join(format!("ddr-test-{}-missing.env.age", std::process::id()));
+
+        // A chave nem é lida quando não há arquivo de segredos
+        let loaded = load_secrets(missing.to_str().unwrap(), Some("/nonexistent/ddr.key"));
+        assert!(loaded.unwrap().is_empty());
+    }
+
+    #[test]
+    fn a_missing_key_file_is_an_error() {
+        let secrets_file = env::temp_dir()
+            .join(format!("ddr-test-{}-nokey.env.age", std::process::id()));
+        let ciphertext = SecretsKey::Identity(x25519::Identity::generate())
+            .encrypt(b"A=1\n")
+            .unwrap();
+        write_private(&secrets_file, ciphertext.as_bytes()).unwrap();
+
+        let result = load_secrets(secrets_file.to_str().unwrap(), Some("/nonexistent/ddr.key"));
+        fs::remove_file(&secrets_file).ok();
+
+        let err = result.unwrap_err();
+        assert!(err.to_string().contains("Não foi possível ler /nonexistent/ddr.key"));
+    }
+}
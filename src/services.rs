@@ -1,141 +1,761 @@
-use std::{collections::HashSet, thread, time::Duration};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    path::PathBuf,
+    thread,
+    time::Instant,
+};
 
 use serde_yaml::{from_value, Mapping, Value};
-use ssh2::Session;
-use reqwest::blocking::Client;
+use sha2::{Digest, Sha256};
+use shell_escape::escape;
+use tracing::{info, info_span, warn, Span};
+
 use crate::{
+    backend::Backend,
+    docker::{container_spec, DockerApi},
+    hooks::{hook_hosts, keep_original_error, run_hooks, HookScope, HookStage},
+    hosts::Sessions,
+    instances::group_services,
     models::{
         ContainerConfig,
+        DeployFilter,
         HealthCheck,
+        Hooks,
+        ListMerge,
+        ListMergeOptions,
         RemoteHealthCheck,
         SSHConfig,
-        ServiceConfig
+        ServiceConfig,
+        SmokeFailure,
+        SmokeTest
     },
+    remotecheck::wait_until_ready,
+    report::{DeployRecords, HealthOutcome, InstanceAction, InstanceReport, Transfer},
+    secrets::{instance_secrets_mount, write_instance_secrets},
+    smoke::run_smoke_tests,
+    sync::{local_sources, sync_sources},
     utils::{
-        docker_load_and_run,
+        discard_previous,
+        docker_load,
+        docker_replace,
+        docker_rollback,
         docker_save,
-        get_session,
-        remove_local_and_remote_file,
+        matches_any,
+        remove_remote_file,
+        run_remote_output,
         scp_send
     }
 };
 
 
+/// Label com o hash das opções do `docker run`, usado para saber se a
+/// instância mudou
+pub const CONFIG_LABEL: &str = "ddr.config";
+
+
+/// O serviço sendo implantado, compartilhado pelas threads dos hosts
+struct ServiceDeploy<'a> {
+    group_name: &'a str,
+    service_name: &'a str,
+    config: &'a ServiceConfig,
+    image_name: &'a str,
+    /// Arquivo da imagem exportada; no alvo local, a imagem já está no Docker
+    tar_file: Option<&'a str>,
+    /// Guarda o contêiner anterior para um possível rollback
+    keep_previous: bool,
+}
+
+
 pub fn handle_group(
-    ssh_config: &SSHConfig,
+    sessions: &mut Sessions,
     group_name: &str,
     deploy_map: &Mapping,
+    filter: &DeployFilter,
+    records: &mut DeployRecords,
     dry_run: bool,
 ) -> anyhow::Result<()> {
 
-    let group_config: Value = deploy_map
-        .get(&Value::String(group_name.to_string()))
-        .cloned()
-        .expect("Group config não encontrado!");
+    let group_config: &Mapping = deploy_map
+        .get(Value::String(group_name.to_string()))
+        .and_then(Value::as_mapping)
+        .expect("Group config não é um mapping");
+
+    let smoke_tests: Vec<SmokeTest> = group_config
+        .get("smoke_tests")
+        .map(|tests| from_value(tests.clone()))
+        .transpose()?
+        .unwrap_or_default();
+
+    let hooks: Option<Hooks> = group_config
+        .get("hooks")
+        .map(|hooks| from_value(hooks.clone()))
+        .transpose()?;
+
+    let selection: (Mapping, HashSet<String>) = select_services(
+        &group_services(group_config),
+        filter
+    )?;
+
+    if selection.0.is_empty() {
+        info!("Nenhum serviço selecionado no grupo {group_name}");
+        return Ok(());
+    }
+
+    // Os hooks do grupo rodam no host padrão, se não indicarem outro
+    let default_host: Option<String> = sessions.inventory()
+        .resolve_hosts(group_name, None, None)
+        .ok()
+        .and_then(|hosts| hosts.into_iter().next());
+    let scope = HookScope {
+        group: group_name,
+        service: None,
+        instance: None,
+        host: default_host.as_deref(),
+        container: None,
+    };
+    if !dry_run {
+        sessions.connect(&hook_hosts(hooks.as_ref(), scope.host))?;
+    }
+
+    // Os contêineres anteriores só são guardados se algum teste pode revertê-los
+    let keep_previous: bool = !dry_run
+        && smoke_tests.iter().any(|test| test.on_failure == SmokeFailure::Rollback);
+
+    let first_record: usize = records.instances.len();
+    let result = run_hooks(sessions, hooks.as_ref(), HookStage::PreDeploy, &scope, dry_run)
+        .and_then(|()| {
+            deploy_waves(
+                sessions,
+                group_name,
+                selection,
+                filter,
+                &mut records.instances,
+                keep_previous,
+                dry_run,
+            )
+        });
+
+    let mut rolled_back: bool = false;
+    let result = result.and_then(|()| {
+        if smoke_tests.is_empty() {
+            return Ok(());
+        }
+
+        let failure = run_smoke_tests(
+            sessions,
+            group_name,
+            deploy_map,
+            &smoke_tests,
+            &mut records.smoke_tests,
+            dry_run,
+        )?;
+
+        match failure {
+            None | Some(SmokeFailure::Warn) => Ok(()),
+            Some(SmokeFailure::Fail) => {
+                anyhow::bail!("Os smoke tests do grupo {group_name} falharam")
+            }
+            Some(SmokeFailure::Rollback) => {
+                rolled_back = true;
+                rollback_group(sessions, &mut records.instances[first_record..])?;
+                anyhow::bail!(
+                    "Os smoke tests do grupo {group_name} falharam; as instâncias foram revertidas"
+                )
+            }
+        }
+    });
+
+    let result = result.and_then(|()| {
+        run_hooks(sessions, hooks.as_ref(), HookStage::PostDeploy, &scope, dry_run)
+    });
+
+    if keep_previous && !rolled_back {
+        let docker_api: bool = sessions.inventory().uses_docker_api();
+        for record in &records.instances[first_record..] {
+            let discarded = sessions.get(&record.host).and_then(|session| {
+                if docker_api {
+                    DockerApi::new(session).discard_previous(&record.instance)
+                } else {
+                    discard_previous(session, &record.instance)
+                }
+            });
+            if let Err(err) = discarded {
+                warn!(
+                    "Não foi possível remover o contêiner anterior de {} em `{}`: {err:#}",
+                    record.instance,
+                    record.host
+                );
+            }
+        }
+    }
+
+    result.map_err(|err| {
+        let hooks = run_hooks(sessions, hooks.as_ref(), HookStage::OnFailure, &scope, dry_run);
+        keep_original_error(err, hooks)
+    })
+}
+
+
+/// Volta as instâncias implantadas pelo grupo aos contêineres anteriores
+fn rollback_group(sessions: &mut Sessions, records: &mut [InstanceReport]) -> anyhow::Result<()> {
+    let docker_api: bool = sessions.inventory().uses_docker_api();
+    for record in records {
+        // O contêiner mantido não tem um anterior para onde voltar
+        if record.action == InstanceAction::Unchanged {
+            continue;
+        }
+        warn!("Revertendo a instância {} em `{}`", record.instance, record.host);
+        let session: &dyn Backend = sessions.get(&record.host)?;
+        if docker_api {
+            DockerApi::new(session).rollback(&record.instance)?;
+        } else {
+            docker_rollback(session, &record.instance)?;
+        }
+        record.action = InstanceAction::RolledBack;
+    }
+
+    Ok(())
+}
+
+
+/// Implanta os serviços selecionados do grupo (`select_services`) em ondas
+/// de dependência
+fn deploy_waves(
+    sessions: &mut Sessions,
+    group_name: &str,
+    selection: (Mapping, HashSet<String>),
+    filter: &DeployFilter,
+    report: &mut Vec<InstanceReport>,
+    keep_previous: bool,
+    dry_run: bool,
+) -> anyhow::Result<()> {
 
     let mut deployed_services: HashSet<String> = HashSet::new();
-    let mut services_to_deploy = group_config
-        .as_mapping()
-        .expect("Group config não é um mapping")
-        .to_owned();
+    let (mut services_to_deploy, filtered_services) = selection;
 
-    let session: Session = get_session(ssh_config)?;
+    let mut wave: usize = 0;
     while !services_to_deploy.is_empty() {
 
         let ready_for_this_wave = resolve_this_wave(
             &services_to_deploy,
             &deployed_services
         )?;
-    
-        for image_name in ready_for_this_wave {
-            let service_config = services_to_deploy
-                .get(&image_name)
-                .unwrap()
-                .clone();
-
-            let tar_file: String = format!(
-                "{}.tar", &image_name.replace("/", "_").replace(":", "_")
-            );
 
-            println!("----------------- DEPLOY DE SERVICE: {image_name} -----------------");
-            println!("Salvando imagem em tar file: {tar_file}");
-            if !dry_run {
-                docker_save(&image_name, &tar_file)?;
+        wave += 1;
+        let wave_span = info_span!("wave", wave);
+        let _wave_entered = wave_span.enter();
+
+        for service_name in ready_for_this_wave {
+            let service_config: ServiceConfig = from_value(
+                services_to_deploy
+                    .get(&service_name)
+                    .unwrap()
+                    .clone()
+            )?;
+
+            let image_name: String = service_config.image
+                .clone()
+                .unwrap_or_else(|| service_name.clone());
+
+            // No alvo local a imagem e os arquivos já estão na máquina
+            let tar_file: Option<String> = (!sessions.inventory().is_local()).then(|| {
+                format!("{}.tar", &image_name.replace("/", "_").replace(":", "_"))
+            });
+
+            let service_span = info_span!("service", service = %service_name, image = %image_name);
+            let _service_entered = service_span.enter();
+
+            info!("Deploy do serviço {service_name} ({image_name})");
+            if let Some(ref tar_file) = tar_file
+                && !dry_run {
+                docker_save(&image_name, tar_file)?;
             }
-            println!("Salvou a imagem {tar_file} em tar file");
 
-            let service_config: ServiceConfig = from_value(service_config)?;
-            
             let instances = service_config.instances.clone();
 
-            if !dry_run {
-                scp_send(
-                    &tar_file,
-                    &format!("/tmp/{}", tar_file),
-                    ssh_config,
+            // Instâncias selecionadas, agrupadas pelos hosts onde rodam
+            let mut placements: BTreeMap<String, Vec<(String, ContainerConfig)>> = BTreeMap::new();
+            // Hosts usados pelos hooks das instâncias
+            let mut hosts: Vec<String> = Vec::new();
+            for (instance_name, instance_value) in instances.into_iter() {
+                let container_config: ContainerConfig = from_value(instance_value)?;
+                let instance_name: String = from_value(instance_name)?;
+
+                if
+                filtered_services.contains(&service_name) &&
+                !filter.instances.is_empty() &&
+                !matches_any(&filter.instances, &instance_name)? {
+                    continue;
+                }
+
+                let resolved = resolve_instance_config_values(&container_config, &service_config)?;
+                let instance_hosts: Vec<String> = sessions.inventory().resolve_hosts(
+                    &instance_name,
+                    resolved.host.as_deref(),
+                    resolved.placement.as_ref()
                 )?;
+
+                for host in instance_hosts {
+                    hosts.extend(hook_hosts(container_config.hooks.as_ref(), Some(&host)));
+                    hosts.push(host.clone());
+                    placements
+                        .entry(host)
+                        .or_default()
+                        .push((instance_name.clone(), container_config.clone()));
+                }
             }
 
-            for (instance_name, instance_value) in instances.into_iter() {
-                let container_config: ContainerConfig = from_value(instance_value.clone())?;
-                let instance_name = instance_name.as_str().unwrap();
+            // Os hooks do serviço rodam, por padrão, no primeiro host dele,
+            // que já recebeu a imagem
+            let service_defaults: ContainerConfig = resolve_instance_config_values(
+                &ContainerConfig::default(),
+                &service_config
+            )?;
+            let scope = HookScope {
+                group: group_name,
+                service: Some(&service_name),
+                instance: None,
+                host: placements.keys().next().map(String::as_str),
+                container: Some((&image_name, &service_defaults)),
+            };
+
+            if !dry_run {
+                hosts.extend(hook_hosts(service_config.hooks.as_ref(), scope.host));
+                sessions.connect(&hosts)?;
+            }
+
+            let service = ServiceDeploy {
+                group_name,
+                service_name: &service_name,
+                config: &service_config,
+                image_name: &image_name,
+                tar_file: tar_file.as_deref(),
+                keep_previous,
+            };
+            let hooks: Option<&Hooks> = service_config.hooks.as_ref();
+            let result = deploy_service(sessions, &placements, &service, &scope, report, dry_run)
+                .and_then(|()| run_hooks(sessions, hooks, HookStage::PostDeploy, &scope, dry_run));
+
+            if let Some(ref tar_file) = tar_file
+                && !dry_run {
+                std::fs::remove_file(tar_file).ok();
+            }
+
+            if let Err(err) = result {
+                let hooks = run_hooks(sessions, hooks, HookStage::OnFailure, &scope, dry_run);
+                return Err(keep_original_error(err, hooks));
+            }
+
+            deployed_services.insert(service_name.clone());
+            deployed_services.insert(image_name);
+            services_to_deploy.remove(&service_name);
+        }
 
-                println!("---------- Deploy de instancia `{instance_name}` ----------");
-                
-                if !dry_run {
-                    handle_instance(
+    }
+
+    Ok(())
+}
+
+
+/// Envia a imagem aos hosts, roda os hooks `pre_deploy` do serviço e sobe
+/// as instâncias. Cada etapa roda nos hosts em paralelo.
+fn deploy_service(
+    sessions: &Sessions,
+    placements: &BTreeMap<String, Vec<(String, ContainerConfig)>>,
+    service: &ServiceDeploy,
+    scope: &HookScope,
+    report: &mut Vec<InstanceReport>,
+    dry_run: bool,
+) -> anyhow::Result<()> {
+
+    // As threads não herdam o span atual
+    let parent_span: Span = Span::current();
+    let in_host_span = |host: &str| {
+        parent_span.in_scope(|| info_span!("host", host = %host))
+    };
+
+    // A imagem só é enviada aos hosts que têm instâncias dela
+    let transfers: Vec<(&String, anyhow::Result<Option<Transfer>>)> = thread::scope(|scope| {
+        let handles: Vec<_> = placements
+            .iter()
+            .map(|(host, host_instances)| {
+                let span = in_host_span(host);
+                scope.spawn(move || {
+                    let _entered = span.entered();
+                    (host, send_image(sessions, host, host_instances, service, dry_run))
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("Thread de deploy entrou em pânico"))
+            .collect()
+    });
+
+    let mut host_transfers: BTreeMap<&String, Option<Transfer>> = BTreeMap::new();
+    let mut failure: Option<anyhow::Error> = None;
+    for (host, result) in transfers {
+        match result {
+            Ok(transfer) => {
+                host_transfers.insert(host, transfer);
+            }
+            Err(err) => {
+                for (instance_name, _) in &placements[host] {
+                    let mut record = InstanceReport::new(
+                        service.group_name,
+                        service.service_name,
                         instance_name,
-                        container_config,
-                        &tar_file,
-                        ssh_config,
-                        &service_config,
-                        &image_name,
-                        &session,
-                    )?;
+                        host,
+                        service.image_name
+                    );
+                    record.fail(&err);
+                    report.push(record);
                 }
+                failure.get_or_insert(err);
+            }
+        }
+    }
+    if let Some(err) = failure {
+        return Err(err);
+    }
 
+    run_hooks(sessions, service.config.hooks.as_ref(), HookStage::PreDeploy, scope, dry_run)?;
+
+    let results: Vec<(Vec<InstanceReport>, anyhow::Result<()>)> = thread::scope(|scope| {
+        let handles: Vec<_> = placements
+            .iter()
+            .map(|(host, host_instances)| {
+                let span = in_host_span(host);
+                let transfer: Option<Transfer> = host_transfers[host];
+                scope.spawn(move || {
+                    let _entered = span.entered();
+                    let mut records: Vec<InstanceReport> = Vec::new();
+                    let result = deploy_instances_on_host(
+                        sessions,
+                        host,
+                        host_instances,
+                        service,
+                        transfer,
+                        &mut records,
+                        dry_run,
+                    );
+                    (records, result)
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("Thread de deploy entrou em pânico"))
+            .collect()
+    });
+
+    let mut failure: Option<anyhow::Error> = None;
+    for (records, result) in results {
+        report.extend(records);
+        if let Err(err) = result {
+            failure.get_or_insert(err);
+        }
+    }
+
+    match failure {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+
+/// Envia ao host os arquivos locais usados pelas instâncias e a imagem, e
+/// carrega a imagem no Docker do host
+fn send_image(
+    sessions: &Sessions,
+    host: &str,
+    instances: &[(String, ContainerConfig)],
+    service: &ServiceDeploy,
+    dry_run: bool,
+) -> anyhow::Result<Option<Transfer>> {
+
+    let Some(tar_file) = service.tar_file else {
+        return Ok(None);
+    };
+
+    // Arquivos locais (env_file e bind mounts) usados pelas instâncias no host
+    let mut sources: Vec<PathBuf> = Vec::new();
+    for (_, container_config) in instances {
+        let container_config = resolve_instance_config_values(container_config, service.config)?;
+        for source in local_sources(&container_config)? {
+            if !sources.contains(&source) {
+                sources.push(source);
             }
-            
-            if !dry_run {
-                remove_local_and_remote_file(
-                    &session,
-                    &tar_file
+        }
+    }
+
+    if dry_run {
+        for source in &sources {
+            info!("Enviaria {} para `{host}`", source.display());
+        }
+        return Ok(None);
+    }
+
+    let session: &dyn Backend = sessions.connected(host);
+    let ssh_config: &SSHConfig = sessions.inventory().ssh_config(host)?;
+    let remote_file: String = format!("/tmp/{tar_file}");
+
+    sync_sources(session, &ssh_config.from_dir, &sources)?;
+
+    // Pela API, a imagem vai direto para o Docker do host, sem arquivo no /tmp
+    if sessions.inventory().uses_docker_api() {
+        let started = Instant::now();
+        let size: u64 = fs::metadata(tar_file)?.len();
+        DockerApi::new(session).load(&mut fs::File::open(tar_file)?, size)?;
+
+        let transfer = Transfer { bytes: size, seconds: started.elapsed().as_secs_f64() };
+        info!(bytes = transfer.bytes, seconds = transfer.seconds, "Imagem {tar_file} carregada");
+        return Ok(Some(transfer));
+    }
+
+    let transfer: Transfer = scp_send(session, tar_file, &remote_file, 0o644)?;
+    docker_load(session, &remote_file)?;
+
+    Ok(Some(transfer))
+}
+
+
+fn deploy_instances_on_host(
+    sessions: &Sessions,
+    host: &str,
+    instances: &[(String, ContainerConfig)],
+    service: &ServiceDeploy,
+    transfer: Option<Transfer>,
+    records: &mut Vec<InstanceReport>,
+    dry_run: bool,
+) -> anyhow::Result<()> {
+
+    if dry_run {
+        for (instance_name, container_config) in instances {
+            info!("Deploy da instância {instance_name} em `{host}`");
+            for stage in [HookStage::PreDeploy, HookStage::PostDeploy] {
+                run_instance_hooks(
+                    sessions,
+                    stage,
+                    instance_name,
+                    container_config,
+                    service,
+                    host,
+                    true
                 )?;
             }
-
-            deployed_services.insert(image_name.clone());
-            services_to_deploy.remove(image_name);
         }
+        return Ok(());
+    }
+
+    let session: &dyn Backend = sessions.connected(host);
+
+    for (instance_name, container_config) in instances {
+        let _entered = info_span!("instance", instance = %instance_name).entered();
+        info!("Deploy da instância {instance_name} em `{host}`");
+
+        let started = Instant::now();
+        let mut record = InstanceReport::new(
+            service.group_name,
+            service.service_name,
+            instance_name,
+            host,
+            service.image_name
+        );
+        record.transfer = transfer;
 
+        let result = handle_instance(
+            instance_name,
+            container_config.clone(),
+            service,
+            sessions,
+            host,
+            &mut record,
+        );
+
+        record.seconds = started.elapsed().as_secs_f64();
+        let result = result.map_err(|err| {
+            record.fail(&err);
+            let hooks = run_instance_hooks(
+                sessions,
+                HookStage::OnFailure,
+                instance_name,
+                container_config,
+                service,
+                host,
+                false
+            );
+            keep_original_error(err, hooks)
+        });
+        records.push(record);
+        result?;
+    }
+
+    if let Some(tar_file) = service.tar_file {
+        remove_remote_file(session, &format!("/tmp/{tar_file}"))?;
     }
 
     Ok(())
 }
 
 
+/// Executa os hooks de uma instância, com a configuração dela já resolvida
+fn run_instance_hooks(
+    sessions: &Sessions,
+    stage: HookStage,
+    instance_name: &str,
+    container_config: &ContainerConfig,
+    service: &ServiceDeploy,
+    host: &str,
+    dry_run: bool,
+) -> anyhow::Result<()> {
+
+    let resolved = resolve_instance_config_values(container_config, service.config)?;
+    let scope = HookScope {
+        group: service.group_name,
+        service: Some(service.service_name),
+        instance: Some(instance_name),
+        host: Some(host),
+        container: Some((service.image_name, &resolved)),
+    };
+
+    run_hooks(sessions, container_config.hooks.as_ref(), stage, &scope, dry_run)
+}
+
+
+/// Nomes pelos quais os serviços de um grupo podem ser referenciados em
+/// `depends_on`: a chave do serviço e a imagem.
+pub fn service_names(services: &Mapping) -> anyhow::Result<HashSet<String>> {
+    let mut names: HashSet<String> = HashSet::new();
+    for (service_name, service_config) in services.iter() {
+        let service_name = service_name
+            .as_str()
+            .expect("Nome de serviço inválido")
+            .to_owned();
+
+        let service: ServiceConfig = from_value(service_config.clone())?;
+        if let Some(image) = service.image {
+            names.insert(image);
+        }
+        names.insert(service_name);
+    }
+
+    Ok(names)
+}
+
+
+/// Seleciona os serviços do grupo que passam pelo filtro. Retorna os
+/// serviços selecionados e o conjunto daqueles escolhidos diretamente pelo
+/// filtro, aos quais o filtro de instâncias se aplica. Dependências incluídas
+/// por `--with-deps` são implantadas com todas as suas instâncias.
+fn select_services(
+    services: &Mapping,
+    filter: &DeployFilter,
+) -> anyhow::Result<(Mapping, HashSet<String>)> {
+
+    if filter.is_empty() {
+        return Ok((services.clone(), HashSet::new()));
+    }
+
+    let mut selected: HashSet<String> = HashSet::new();
+    for (service_name, service_config) in services.iter() {
+        let service_name = service_name
+            .as_str()
+            .expect("Nome de serviço inválido");
+        let service: ServiceConfig = from_value(service_config.clone())?;
+
+        let service_matches = filter.services.is_empty()
+            || matches_any(&filter.services, service_name)?
+            || match service.image {
+                Some(ref image) => matches_any(&filter.services, image)?,
+                None => false,
+            };
+
+        let mut instance_matches = filter.instances.is_empty();
+        for instance_name in service.instances.keys().filter_map(Value::as_str) {
+            if instance_matches {
+                break;
+            }
+            instance_matches = matches_any(&filter.instances, instance_name)?;
+        }
+
+        if service_matches && instance_matches {
+            selected.insert(service_name.to_owned());
+        }
+    }
+
+    let filtered: HashSet<String> = selected.clone();
+
+    if filter.with_deps {
+        let mut to_visit: Vec<String> = selected.iter().cloned().collect();
+        while let Some(current) = to_visit.pop() {
+            let service: ServiceConfig = from_value(services.get(&current).unwrap().clone())?;
+            for dep in service.depends_on.unwrap_or_default() {
+                for (service_name, service_config) in services.iter() {
+                    let service_name = service_name.as_str().unwrap().to_owned();
+                    let dep_service: ServiceConfig = from_value(service_config.clone())?;
+                    let is_dep = service_name == dep || dep_service.image.as_ref() == Some(&dep);
+
+                    if is_dep && selected.insert(service_name.clone()) {
+                        to_visit.push(service_name);
+                    }
+                }
+            }
+        }
+    }
+
+    let selected_services: Mapping = services
+        .iter()
+        .filter(|(name, _)| name.as_str().is_some_and(|name| selected.contains(name)))
+        .map(|(name, config)| (name.clone(), config.clone()))
+        .collect();
+
+    Ok((selected_services, filtered))
+}
+
+
+/// Serviços do grupo na ordem em que são implantados (ondas de dependência)
+pub fn service_order(services: &Mapping) -> anyhow::Result<Vec<String>> {
+    let mut pending: Mapping = services.clone();
+    let mut order: Vec<String> = Vec::new();
+
+    while !pending.is_empty() {
+        for service_name in resolve_this_wave(&pending, &HashSet::new())? {
+            pending.remove(&service_name);
+            order.push(service_name);
+        }
+    }
+
+    Ok(order)
+}
+
+
 fn resolve_this_wave(
     services_to_deploy: &Mapping,
     deployed_services: &HashSet<String>
 ) -> anyhow::Result<Vec<String>> {
 
+    // Dependências que não pertencem a este grupo são consideradas satisfeitas
+    let pending: HashSet<String> = service_names(services_to_deploy)?;
+
     let mut ready_for_this_wave: Vec<String> = Vec::new();
-    for (image_name, service_config) in services_to_deploy.iter() {
-        let mut image_name = image_name
+    for (service_name, service_config) in services_to_deploy.iter() {
+        let service_name = service_name
             .as_str()
             .expect("Nenhuma image econtrada")
             .to_owned();
 
         let service: ServiceConfig = from_value(service_config.clone())?;
-        if let Some(image) =  service.image {
-            image_name = image;
-        }
         let dependencies = service.depends_on.clone().unwrap_or_default();
-        let all_deps_ready = if dependencies.is_empty() { true } else {
-            dependencies.iter().all(|dep| deployed_services.contains(dep))
-        };
+        let all_deps_ready = dependencies
+            .iter()
+            .all(|dep| deployed_services.contains(dep) || !pending.contains(dep));
+
         if all_deps_ready {
-            ready_for_this_wave
-            .push(image_name);
+            ready_for_this_wave.push(service_name);
         }
     }
     if ready_for_this_wave.is_empty() {
@@ -153,41 +773,121 @@ fn resolve_this_wave(
 fn handle_instance(
     instance_name: &str,
     container_config: ContainerConfig,
-    tar_file: &str,
-    ssh_config: &SSHConfig,
-    service_config: &ServiceConfig,
-    image_name: &str,
-    session: &Session,
+    service: &ServiceDeploy,
+    sessions: &Sessions,
+    host: &str,
+    record: &mut InstanceReport,
 ) -> anyhow::Result<()> {
 
-    let cmd: String = resolve_instace_command(
-        &instance_name,
-        &container_config,
-        service_config,
-        image_name
-    )?;
+    let session: &dyn Backend = sessions.connected(host);
+    let ssh_config: &SSHConfig = sessions.inventory().ssh_config(host)?;
+    let docker_api: Option<DockerApi> =
+        sessions.inventory().uses_docker_api().then(|| DockerApi::new(session));
+
+    let resolved = resolve_instance_config_values(&container_config, service.config)?;
+    let hash: String = config_hash(
+        &detached_command(instance_name, &resolved, service.image_name),
+        &resolved
+    );
+
+    let identity = || match docker_api {
+        Some(ref api) => Ok(api
+            .inspect(instance_name)?
+            .map(|(image, config_hash, running)| format!("{image} {config_hash} {running}"))),
+        None => container_identity(session, instance_name),
+    };
 
-    println!("Instance name: {instance_name}");
+    let previous: Option<String> = identity()?;
 
-    docker_load_and_run(
-        &session,
-        &format!("/tmp/{}", tar_file),
-        cmd,
-        instance_name,
-        &ssh_config
-    )?;
+    // Os segredos são regravados a cada deploy, e o contêiner que já roda
+    // ficaria com a montagem do diretório apagado; ele é sempre recriado
+    let unchanged: bool = previous.is_some()
+        && resolved.secrets.is_none()
+        && previous == expected_identity(session, docker_api.as_ref(), service.image_name, &hash)?;
+
+    let instance_hooks = |stage: HookStage| {
+        run_instance_hooks(sessions, stage, instance_name, &container_config, service, host, false)
+    };
+
+    instance_hooks(HookStage::PreDeploy)?;
+
+    if unchanged {
+        info!("Instância {instance_name} sem alterações; contêiner mantido");
+    } else {
+        if let Some(ref secrets) = resolved.secrets {
+            write_instance_secrets(
+                session,
+                instance_name,
+                secrets,
+                resolved.secrets_owner.as_deref()
+            )?;
+        }
+
+        if previous.is_some() {
+            instance_hooks(HookStage::PreStop)?;
+        }
 
-    if let Some(check_health) = &container_config.remotecheck {
-        check_instance(
+        match docker_api {
+            Some(ref api) => {
+                let spec = container_spec(
+                    session,
+                    &ssh_config.from_dir,
+                    &resolved,
+                    service.image_name,
+                    &hash,
+                    resolved.secrets.is_some().then_some(instance_name)
+                )?;
+                api.replace(instance_name, &spec, service.keep_previous)?;
+            }
+            None => {
+                let cmd: String = resolve_instace_command(
+                    instance_name,
+                    &container_config,
+                    service.config,
+                    service.image_name,
+                    RunMode::Detached
+                )?;
+                docker_replace(session, cmd, instance_name, ssh_config, service.keep_previous)?;
+            }
+        }
+    }
+
+    let current: Option<String> = identity()?;
+    record.image_id = current
+        .as_deref()
+        .and_then(|identity| identity.split_whitespace().next())
+        .map(str::to_owned);
+    record.action = match previous {
+        None => InstanceAction::Created,
+        Some(_) if unchanged => InstanceAction::Unchanged,
+        Some(ref previous) => {
+            // Sem o estado: recriar um contêiner parado não muda a configuração
+            let configuration = |identity: &str| {
+                identity.rsplit_once(' ').map(|(configuration, _)| configuration.to_owned())
+            };
+            record.config_changed =
+                Some(configuration(previous) != current.as_deref().and_then(configuration));
+            InstanceAction::Recreated
+        }
+    };
+
+    if let Some(check_health) = &resolved.remotecheck {
+        let checked = check_instance(
             instance_name,
             check_health,
             ssh_config,
-            &session,
-            tar_file
-        )?;
+            session,
+            service.tar_file
+        );
+        record.healthcheck = match checked {
+            Ok(true) => HealthOutcome::Passed,
+            Ok(false) => HealthOutcome::Skipped,
+            Err(_) => HealthOutcome::Failed,
+        };
+        checked?;
     }
 
-    Ok(())
+    instance_hooks(HookStage::PostDeploy)
 
 }
 
@@ -196,56 +896,87 @@ fn check_instance(
     instance_name: &str,
     check_health: &RemoteHealthCheck,
     ssh_config: &SSHConfig,
-    session: &Session,
-    remote_file: & str
-) -> anyhow::Result<()> {
+    session: &dyn Backend,
+    tar_file: Option<&str>
+) -> anyhow::Result<bool> {
+
+    let checked = wait_until_ready(check_health, instance_name, ssh_config, session);
+    if let Some(tar_file) = tar_file
+        && checked.is_err() {
+        remove_remote_file(session, &format!("/tmp/{tar_file}"))?;
+    }
 
-    if
-    let Some(endpoint) = check_health.endpoint.clone() &&
-    let Some(port) = check_health.port {
+    checked
+}
 
-        let url: String = format!(
-            "http://{}:{}{}",
-            ssh_config.host,
-            port,
-            endpoint
-        );
-        
-        let client: Client = Client::new();
-        let mut success: bool = false;
-        for _ in 0..30 {
-            if let Ok(resp) = client.get(&url).send() {
-                if resp.status().is_success() {
-                    success = true;
-                    break;
-                }
-            }
-            thread::sleep(Duration::from_secs(1));
-        }
-        if !success {
-            remove_local_and_remote_file(session, remote_file)?;
-            panic!(
-                "A instância {} não respondeu no endpoint {}",
-                instance_name,
-                url
-            );
+
+/// Imagem, hash das opções do contêiner da instância e se ele está rodando
+/// (`<imagem> <hash> <true|false>`), ou `None` se ele não existir
+fn container_identity(
+    session: &dyn Backend,
+    instance_name: &str,
+) -> anyhow::Result<Option<String>> {
+    let output: String = run_remote_output(
+        session,
+        &format!(
+            "docker inspect -f '{{{{.Image}}}} {{{{index .Config.Labels \"{CONFIG_LABEL}\"}}}} \
+             {{{{.State.Running}}}}' {} 2>/dev/null || true",
+            escape(instance_name.into())
+        ),
+    )?;
+
+    let identity: &str = output.trim();
+    Ok((!identity.is_empty()).then(|| identity.to_owned()))
+}
+
+
+/// Identidade (como em `container_identity`) que o contêiner da instância
+/// teria rodando a imagem `image_name` com as opções de hash `hash`, ou
+/// `None` se a imagem não existir no host
+fn expected_identity(
+    session: &dyn Backend,
+    docker_api: Option<&DockerApi>,
+    image_name: &str,
+    hash: &str,
+) -> anyhow::Result<Option<String>> {
+    let image_id: Option<String> = match docker_api {
+        Some(api) => api.image_id(image_name)?,
+        None => {
+            let output: String = run_remote_output(
+                session,
+                &format!(
+                    "docker image inspect -f '{{{{.Id}}}}' {} 2>/dev/null || true",
+                    escape(image_name.into())
+                ),
+            )?;
+            Some(output.trim().to_owned()).filter(|id| !id.is_empty())
         }
-        println!(
-            "Instância {} ok em {}",
-            instance_name,
-            url
-        );
-    }
+    };
 
-    Ok(())
+    Ok(image_id.map(|image_id| format!("{image_id} {hash} true")))
 }
 
 
-fn resolve_instace_command(
+/// Como o contêiner do `docker run` é executado
+#[derive(Debug, Clone, Copy)]
+pub enum RunMode<'a> {
+    /// Em segundo plano, como uma instância do deploy
+    Detached,
+    /// Avulso e removido ao terminar, com o comando informado no lugar do
+    /// `command` configurado
+    OneOff {
+        command: Option<&'a str>,
+        tty: bool,
+    },
+}
+
+
+pub fn resolve_instace_command(
     instance_name: &str,
     container_config: &ContainerConfig,
     service_config: &ServiceConfig,
     image_name: &str,
+    mode: RunMode,
 ) -> anyhow::Result<String> {
 
     let container_config: ContainerConfig = resolve_instance_config_values(
@@ -253,8 +984,110 @@ fn resolve_instace_command(
         service_config
     )?;
 
-    // Construir o comando principal
+    if let RunMode::OneOff { command, tty } = mode {
+        let command: Option<&str> = command.or(container_config.command.as_deref());
+        return Ok(
+            one_off_command(instance_name, &container_config, image_name, &[], command, tty)
+        );
+    }
+
+    let cmd: String = detached_command(instance_name, &container_config, image_name);
+    let cmd = cmd.replacen(
+        "docker run -d",
+        &format!(
+            "docker run -d --label {CONFIG_LABEL}={}",
+            config_hash(&cmd, &container_config)
+        ),
+        1
+    );
+
+    Ok(cmd)
+
+}
+
+
+/// Comando `docker run -d` da instância, sem a label `ddr.config`, a partir
+/// da configuração já resolvida
+fn detached_command(
+    instance_name: &str,
+    container_config: &ContainerConfig,
+    image_name: &str,
+) -> String {
     let mut cmd: String = format!("docker run -d --name {}", instance_name);
+    cmd += &docker_run_options(instance_name, container_config);
+
+    cmd += &format!(" {}", image_name);
+    if let Some(ref command) = container_config.command {
+        cmd += &format!(" {}", command);
+    }
+
+    cmd
+}
+
+
+/// Valor da label `ddr.config` para o comando da instância. Os dois modos
+/// de falar com o Docker usam o mesmo hash, então trocar de modo não recria
+/// as instâncias. O hash inclui o conteúdo dos `env_file` locais, que são
+/// lidos só na criação do contêiner, mas não os valores dos segredos.
+fn config_hash(detached_command: &str, container_config: &ContainerConfig) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(detached_command.as_bytes());
+
+    for env_file in container_config.env_file.iter().flatten() {
+        if let Ok(content) = fs::read(env_file) {
+            hasher.update([0]);
+            hasher.update(&content);
+        }
+    }
+
+    format!("{:x}", hasher.finalize())[..16].to_owned()
+}
+
+
+/// Comando de um contêiner avulso (`docker run --rm`) com a imagem, a rede,
+/// o ambiente e os volumes de uma configuração já resolvida. `command`
+/// substitui o comando da imagem. Os segredos, se houver, são montados do
+/// diretório de `container_name`.
+pub fn one_off_command(
+    container_name: &str,
+    container_config: &ContainerConfig,
+    image_name: &str,
+    extra_env: &[String],
+    command: Option<&str>,
+    tty: bool,
+) -> String {
+
+    // Um contêiner avulso não reinicia, não tem healthcheck e não disputa as
+    // portas com as instâncias
+    let container_config = ContainerConfig {
+        restart: None,
+        healthcheck: None,
+        ports: None,
+        ..container_config.clone()
+    };
+
+    let mut cmd: String = String::from("docker run --rm");
+    if tty {
+        cmd += " -it";
+    }
+    cmd += &format!(" --name {}", container_name);
+    cmd += &docker_run_options(container_name, &container_config);
+    for variable in extra_env {
+        cmd += &format!(" -e {}", escape(variable.as_str().into()));
+    }
+    cmd += &format!(" {}", image_name);
+    if let Some(command) = command {
+        cmd += &format!(" {}", command);
+    }
+
+    cmd
+}
+
+
+/// Opções do `docker run` vindas da configuração já resolvida, na ordem
+/// usada pelo hash da label `ddr.config`
+fn docker_run_options(instance_name: &str, container_config: &ContainerConfig) -> String {
+    let mut cmd: String = String::new();
 
     if let Some(ref net) = container_config.network_mode {
         cmd += &format!(" --network {}", net);
@@ -282,90 +1115,220 @@ fn resolve_instace_command(
         }
     }
 
-    if let Some(ref hc) = container_config.healthcheck {
-        let cmd_string: String = build_health_cmd(hc);
-        if !cmd_string.is_empty() {
-            cmd = format!(
-                "{} --health-cmd='{}' --health-interval={} --health-timeout={} --health-retries={}",
-                cmd,
-                cmd_string,
-                hc.interval,
-                hc.timeout,
-                hc.retries
-            );
+    if let Some(ref ports) = container_config.ports {
+        for p in ports {
+            cmd += &format!(" -p {}", p);
         }
     }
 
-    cmd += &format!(" {}", image_name);
-    if let Some(ref command) = container_config.command {
-        cmd += &format!(" {}", command);
+    if container_config.secrets.is_some() {
+        cmd += &format!(" {}", instance_secrets_mount(instance_name));
     }
 
-    Ok(cmd)
+    if let Some(ref hc) = container_config.healthcheck {
+        let cmd_string: String = build_health_cmd(hc);
+        if !cmd_string.is_empty() {
+            cmd += &format!(" --health-cmd='{}'", cmd_string);
+        }
+        if let Some(ref interval) = hc.interval {
+            cmd += &format!(" --health-interval={}", interval);
+        }
+        if let Some(ref timeout) = hc.timeout {
+            cmd += &format!(" --health-timeout={}", timeout);
+        }
+        if let Some(retries) = hc.retries {
+            cmd += &format!(" --health-retries={}", retries);
+        }
+    }
 
+    cmd
 }
 
 
-fn resolve_instance_config_values(
+/// Completa a configuração da instância com os padrões do serviço, campo a
+/// campo: o que a instância define tem prioridade, `remotecheck` e
+/// `healthcheck` são mesclados por campo, `secrets` por nome e as listas
+/// `environment` e `volumes` conforme `list_merge`. Deve receber sempre a
+/// configuração original da instância, nunca uma já resolvida.
+pub fn resolve_instance_config_values(
     container_config: &ContainerConfig,
     service_config: &ServiceConfig,
 ) -> anyhow::Result<ContainerConfig> {
 
-    let mut container_config: ContainerConfig = container_config.clone();
+    let instance: ContainerConfig = container_config.clone();
+    let service: &ServiceConfig = service_config;
+
+    let list_merge = ListMergeOptions {
+        environment: inherit(
+            instance.list_merge.and_then(|options| options.environment),
+            &service.list_merge.and_then(|options| options.environment),
+        ),
+        volumes: inherit(
+            instance.list_merge.and_then(|options| options.volumes),
+            &service.list_merge.and_then(|options| options.volumes),
+        ),
+    };
 
-    if let Some(ref mut check_container) = container_config.remotecheck {
-        if let Some(ref check_service) = service_config.remotecheck {
-            if let Some(port) = check_service.port {
-                check_container.port = Some(port);
-            }
-        } else {
-            if let Some(ref check_service) = service_config.remotecheck {
-                if
-                let Some(port) = check_service.port &&
-                let Some(ref endpoint) = check_service.endpoint {
-                    container_config.remotecheck = Some(
-                        RemoteHealthCheck {
-                            port: Some(port),
-                            endpoint: Some(endpoint.clone())
-                        }
-                    )
-                }
+    // host e placement formam um par: se a instância define um deles, os
+    // dois vêm dela
+    let (host, placement) = if instance.host.is_some() || instance.placement.is_some() {
+        (instance.host, instance.placement)
+    } else {
+        (service.host.clone(), service.placement.clone())
+    };
+
+    Ok(ContainerConfig {
+        network_mode: inherit(instance.network_mode, &service.network_mode),
+        restart: inherit(instance.restart, &service.restart),
+        env_file: inherit(instance.env_file, &service.env_file),
+        volumes: merge_list(
+            instance.volumes,
+            &service.volumes,
+            list_merge.volumes.unwrap_or_default(),
+            volume_target,
+        ),
+        ports: inherit(instance.ports, &service.ports),
+        environment: merge_list(
+            instance.environment,
+            &service.environment,
+            list_merge.environment.unwrap_or_default(),
+            variable_name,
+        ),
+        command: inherit(instance.command, &service.command),
+        remotecheck: merge_nested(instance.remotecheck, &service.remotecheck, merge_remotecheck),
+        healthcheck: merge_nested(instance.healthcheck, &service.healthcheck, merge_healthcheck),
+        host,
+        placement,
+        remote_only: inherit(instance.remote_only, &service.remote_only),
+        secrets: merge_nested(instance.secrets, &service.secrets, |mut instance, service| {
+            for (name, value) in service {
+                instance.entry(name.clone()).or_insert_with(|| value.clone());
             }
-        }
+            instance
+        }),
+        secrets_owner: inherit(instance.secrets_owner, &service.secrets_owner),
+        list_merge: Some(list_merge),
+        // Os hooks do serviço rodam uma vez para o serviço, não por instância
+        hooks: instance.hooks,
+    })
+
+}
+
+
+/// Valor da instância ou, na falta dele, o do serviço
+fn inherit<T: Clone>(instance: Option<T>, service: &Option<T>) -> Option<T> {
+    instance.or_else(|| service.clone())
+}
+
+
+/// Mescla valores compostos quando instância e serviço definem os dois
+fn merge_nested<T: Clone>(
+    instance: Option<T>,
+    service: &Option<T>,
+    merge: impl FnOnce(T, &T) -> T,
+) -> Option<T> {
+    match (instance, service) {
+        (Some(instance), Some(service)) => Some(merge(instance, service)),
+        (instance, service) => instance.or_else(|| service.clone()),
+    }
+}
+
+
+fn merge_remotecheck(
+    instance: RemoteHealthCheck,
+    service: &RemoteHealthCheck,
+) -> RemoteHealthCheck {
+    let service: RemoteHealthCheck = service.clone();
+
+    RemoteHealthCheck {
+        probe: instance.probe.or(service.probe),
+        port: instance.port.or(service.port),
+        endpoint: instance.endpoint.or(service.endpoint),
+        https: instance.https.or(service.https),
+        insecure: instance.insecure.or(service.insecure),
+        status: instance.status.or(service.status),
+        body: instance.body.or(service.body),
+        headers: instance.headers.or(service.headers),
+        command: instance.command.or(service.command),
+        from: instance.from.or(service.from),
+        interval: instance.interval.or(service.interval),
+        timeout: instance.timeout.or(service.timeout),
+        retries: instance.retries.or(service.retries),
+        start_period: instance.start_period.or(service.start_period),
+        success_threshold: instance.success_threshold.or(service.success_threshold),
     }
+}
 
-    container_config.environment = container_config.environment
-        .or_else(|| service_config.environment.clone());
 
-    container_config.network_mode = container_config.network_mode
-        .or_else(|| service_config.network_mode.clone());
+fn merge_healthcheck(instance: HealthCheck, service: &HealthCheck) -> HealthCheck {
+    let service: HealthCheck = service.clone();
+
+    HealthCheck {
+        test: instance.test.or(service.test),
+        interval: instance.interval.or(service.interval),
+        timeout: instance.timeout.or(service.timeout),
+        retries: instance.retries.or(service.retries),
+    }
+}
 
-    container_config.restart = container_config.restart
-        .or_else(|| service_config.restart.clone());
 
-    container_config.env_file = container_config.env_file
-        .or_else(|| service_config.env_file.clone());
+/// Combina as listas da instância e do serviço conforme o modo. No modo
+/// `merge`, `key` identifica itens equivalentes.
+fn merge_list(
+    instance: Option<Vec<String>>,
+    service: &Option<Vec<String>>,
+    mode: ListMerge,
+    key: fn(&str) -> &str,
+) -> Option<Vec<String>> {
+
+    let (instance, service) = match (instance, service) {
+        (Some(instance), Some(service)) => (instance, service),
+        (instance, service) => return instance.or_else(|| service.clone()),
+    };
+
+    match mode {
+        ListMerge::Replace => Some(instance),
+        ListMerge::Append => Some(service.iter().cloned().chain(instance).collect()),
+        ListMerge::Merge => {
+            let mut merged: Vec<String> = service.clone();
+            for item in instance {
+                match merged.iter().position(|existing| key(existing) == key(&item)) {
+                    Some(index) => merged[index] = item,
+                    None => merged.push(item),
+                }
+            }
+            Some(merged)
+        }
+    }
+}
+
 
-    container_config.volumes = container_config.volumes
-        .or_else(|| service_config.volumes.clone());
+/// Nome da variável em `NOME=valor`
+fn variable_name(variable: &str) -> &str {
+    variable.split('=').next().unwrap_or(variable)
+}
 
-    return Ok(container_config);
 
+/// Destino do volume em `origem:destino[:opções]` (ou o próprio volume
+/// anônimo)
+fn volume_target(volume: &str) -> &str {
+    volume.split(':').nth(1).unwrap_or(volume)
 }
 
 
-fn build_health_cmd(hc: &HealthCheck) -> String {
-    if hc.test.is_empty() {
+pub fn build_health_cmd(hc: &HealthCheck) -> String {
+    let test: &[String] = hc.test.as_deref().unwrap_or_default();
+    if test.is_empty() {
         return String::new();
     }
 
-    let cmd_parts: &[String] = if hc.test[0] == "CMD" || hc.test[0] == "CMD-SHELL" {
-        &hc.test[1..]
+    let cmd_parts: &[String] = if test[0] == "CMD" || test[0] == "CMD-SHELL" {
+        &test[1..]
     } else {
-        &hc.test[..]
+        test
     };
 
-    let cmd_string = cmd_parts
+    cmd_parts
         .iter()
         .map(|s| {
             if s.contains(' ') {
@@ -375,7 +1338,299 @@ fn build_health_cmd(hc: &HealthCheck) -> String {
             }
         })
         .collect::<Vec<String>>()
-        .join(" ");
+        .join(" ")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::models::{ProbeOrigin, ProbeType};
+
+    fn resolve(service: &str, instance: &str) -> ContainerConfig {
+        let service: ServiceConfig = serde_yaml::from_str(&format!("{service}\ninstances: {{}}"))
+            .expect("serviço inválido");
+        let instance: ContainerConfig = serde_yaml::from_str(instance)
+            .expect("instância inválida");
+
+        resolve_instance_config_values(&instance, &service).unwrap()
+    }
+
+    fn strings(items: &[&str]) -> Option<Vec<String>> {
+        Some(items.iter().map(|item| item.to_string()).collect())
+    }
+
+    #[test]
+    fn scalars_are_inherited_from_the_service() {
+        let config = resolve(
+            "network_mode: host\nrestart: always\ncommand: serve",
+            "{}",
+        );
+
+        assert_eq!(config.network_mode.as_deref(), Some("host"));
+        assert_eq!(config.restart.as_deref(), Some("always"));
+        assert_eq!(config.command.as_deref(), Some("serve"));
+    }
+
+    #[test]
+    fn instance_scalars_take_precedence() {
+        let config = resolve(
+            "network_mode: host\nrestart: always\ncommand: serve",
+            "{network_mode: bridge, restart: 'no', command: migrate}",
+        );
+
+        assert_eq!(config.network_mode.as_deref(), Some("bridge"));
+        assert_eq!(config.restart.as_deref(), Some("no"));
+        assert_eq!(config.command.as_deref(), Some("migrate"));
+    }
+
+    #[test]
+    fn env_file_and_remote_only_are_replaced_by_the_instance() {
+        let config = resolve(
+            "env_file: [base.env]\nremote_only: [./data]",
+            "{}",
+        );
+        assert_eq!(config.env_file, strings(&["base.env"]));
+        assert_eq!(config.remote_only, strings(&["./data"]));
+
+        let config = resolve(
+            "env_file: [base.env]\nremote_only: [./data]",
+            "{env_file: [api.env], remote_only: [./cache]}",
+        );
+        assert_eq!(config.env_file, strings(&["api.env"]));
+        assert_eq!(config.remote_only, strings(&["./cache"]));
+    }
+
+    #[test]
+    fn ports_are_replaced_by_the_instance() {
+        let config = resolve("ports: ['8080:80']", "{}");
+        assert_eq!(config.ports, strings(&["8080:80"]));
+
+        let config = resolve("ports: ['8080:80']", "{ports: ['8081:80']}");
+        assert_eq!(config.ports, strings(&["8081:80"]));
+    }
+
+    #[test]
+    fn remotecheck_combines_service_endpoint_with_instance_port() {
+        let config = resolve(
+            "remotecheck: {endpoint: /ok}",
+            "{remotecheck: {port: 8001}}",
+        );
+
+        let check = config.remotecheck.unwrap();
+        assert_eq!(check.endpoint.as_deref(), Some("/ok"));
+        assert_eq!(check.port, Some(8001));
+    }
+
+    #[test]
+    fn remotecheck_is_inherited_when_the_instance_has_none() {
+        let config = resolve("remotecheck: {port: 80, endpoint: /health}", "{}");
+
+        let check = config.remotecheck.unwrap();
+        assert_eq!(check.port, Some(80));
+        assert_eq!(check.endpoint.as_deref(), Some("/health"));
+    }
+
+    #[test]
+    fn remotecheck_instance_fields_take_precedence() {
+        let config = resolve(
+            "remotecheck:\n  type: http\n  port: 80\n  endpoint: /health\n  status: 200\n  \
+             body: ok\n  headers: {Host: api}\n  https: true\n  insecure: true\n  \
+             from: remote\n  interval: 2s\n  timeout: 3s\n  retries: 5\n  \
+             start_period: 10s\n  success_threshold: 2",
+            "{remotecheck: {type: tcp, port: 9000, retries: 7, from: local}}",
+        );
 
-    cmd_string
+        let check = config.remotecheck.unwrap();
+        assert_eq!(check.probe, Some(ProbeType::Tcp));
+        assert_eq!(check.port, Some(9000));
+        assert_eq!(check.retries, Some(7));
+        assert_eq!(check.from, Some(ProbeOrigin::Local));
+        assert_eq!(check.endpoint.as_deref(), Some("/health"));
+        assert_eq!(check.status, Some(200));
+        assert_eq!(check.body.as_deref(), Some("ok"));
+        assert_eq!(check.headers.unwrap().get("Host").map(String::as_str), Some("api"));
+        assert_eq!(check.https, Some(true));
+        assert_eq!(check.insecure, Some(true));
+        assert_eq!(check.interval, Some(Duration::from_secs(2)));
+        assert_eq!(check.timeout, Some(Duration::from_secs(3)));
+        assert_eq!(check.start_period, Some(Duration::from_secs(10)));
+        assert_eq!(check.success_threshold, Some(2));
+    }
+
+    #[test]
+    fn remotecheck_command_is_inherited() {
+        let config = resolve(
+            "remotecheck: {type: exec, command: 'pg_isready'}",
+            "{remotecheck: {timeout: 1s}}",
+        );
+
+        let check = config.remotecheck.unwrap();
+        assert_eq!(check.probe, Some(ProbeType::Exec));
+        assert_eq!(check.command.as_deref(), Some("pg_isready"));
+        assert_eq!(check.timeout, Some(Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn healthcheck_is_merged_field_by_field() {
+        let config = resolve(
+            "healthcheck:\n  test: [CMD, curl, -f, localhost]\n  interval: 30s\n  \
+             timeout: 5s\n  retries: 3",
+            "{healthcheck: {interval: 10s, retries: 5}}",
+        );
+
+        let healthcheck = config.healthcheck.unwrap();
+        assert_eq!(healthcheck.test, strings(&["CMD", "curl", "-f", "localhost"]));
+        assert_eq!(healthcheck.interval.as_deref(), Some("10s"));
+        assert_eq!(healthcheck.timeout.as_deref(), Some("5s"));
+        assert_eq!(healthcheck.retries, Some(5));
+    }
+
+    #[test]
+    fn healthcheck_only_sets_the_declared_flags() {
+        let service: ServiceConfig = serde_yaml::from_str(
+            "image: api\nhealthcheck: {interval: 10s}\ninstances: {}"
+        ).unwrap();
+        let instance: ContainerConfig = serde_yaml::from_str("{}").unwrap();
+
+        let cmd = resolve_instace_command("api1", &instance, &service, "api", RunMode::Detached)
+            .unwrap();
+        assert!(cmd.contains(" --health-interval=10s "));
+        assert!(!cmd.contains("--health-cmd"));
+        assert!(!cmd.contains("--health-timeout"));
+        assert!(!cmd.contains("--health-retries"));
+    }
+
+    #[test]
+    fn environment_is_replaced_by_default() {
+        let config = resolve("environment: [A=1, B=2]", "{environment: [B=3]}");
+        assert_eq!(config.environment, strings(&["B=3"]));
+
+        let config = resolve("environment: [A=1, B=2]", "{}");
+        assert_eq!(config.environment, strings(&["A=1", "B=2"]));
+    }
+
+    #[test]
+    fn environment_can_be_appended() {
+        let config = resolve(
+            "environment: [A=1, B=2]",
+            "{environment: [B=3], list_merge: {environment: append}}",
+        );
+        assert_eq!(config.environment, strings(&["A=1", "B=2", "B=3"]));
+    }
+
+    #[test]
+    fn environment_can_be_merged_by_variable_name() {
+        let config = resolve(
+            "environment: [A=1, B=2]",
+            "{environment: [B=3, C=4], list_merge: {environment: merge}}",
+        );
+        assert_eq!(config.environment, strings(&["A=1", "B=3", "C=4"]));
+    }
+
+    #[test]
+    fn volumes_are_replaced_by_default() {
+        let config = resolve("volumes: [./conf:/etc/app]", "{volumes: [data:/data]}");
+        assert_eq!(config.volumes, strings(&["data:/data"]));
+
+        let config = resolve("volumes: [./conf:/etc/app]", "{}");
+        assert_eq!(config.volumes, strings(&["./conf:/etc/app"]));
+    }
+
+    #[test]
+    fn volumes_can_be_appended() {
+        let config = resolve(
+            "volumes: [./conf:/etc/app]",
+            "{volumes: [data:/data], list_merge: {volumes: append}}",
+        );
+        assert_eq!(config.volumes, strings(&["./conf:/etc/app", "data:/data"]));
+    }
+
+    #[test]
+    fn volumes_can_be_merged_by_target() {
+        let config = resolve(
+            "volumes: [./conf:/etc/app, logs:/var/log]",
+            "{volumes: ['./api-conf:/etc/app:ro', /cache], list_merge: {volumes: merge}}",
+        );
+        assert_eq!(
+            config.volumes,
+            strings(&["./api-conf:/etc/app:ro", "logs:/var/log", "/cache"])
+        );
+    }
+
+    #[test]
+    fn list_merge_is_inherited_and_overridable_per_list() {
+        let service = "environment: [A=1]\nvolumes: [a:/a]\n\
+                       list_merge: {environment: merge, volumes: append}";
+
+        let config = resolve(service, "{environment: [B=2], volumes: [b:/b]}");
+        assert_eq!(config.environment, strings(&["A=1", "B=2"]));
+        assert_eq!(config.volumes, strings(&["a:/a", "b:/b"]));
+
+        let config = resolve(
+            service,
+            "{environment: [B=2], volumes: [b:/b], list_merge: {volumes: replace}}",
+        );
+        assert_eq!(config.environment, strings(&["A=1", "B=2"]));
+        assert_eq!(config.volumes, strings(&["b:/b"]));
+    }
+
+    #[test]
+    fn secrets_owner_is_inherited() {
+        assert_eq!(resolve("secrets_owner: '1000'", "{}").secrets_owner.as_deref(), Some("1000"));
+        assert_eq!(
+            resolve("secrets_owner: '1000'", "{secrets_owner: '0:0'}").secrets_owner.as_deref(),
+            Some("0:0")
+        );
+    }
+
+    #[test]
+    fn secrets_are_merged_by_name() {
+        let config = resolve(
+            "secrets: {db: service-db, api: service-api}",
+            "{secrets: {db: instance-db}}",
+        );
+
+        let secrets = config.secrets.unwrap();
+        assert_eq!(secrets.get("db").map(String::as_str), Some("instance-db"));
+        assert_eq!(secrets.get("api").map(String::as_str), Some("service-api"));
+    }
+
+    #[test]
+    fn host_and_placement_are_inherited_together() {
+        let config = resolve("host: web1\nplacement: {labels: {role: web}}", "{}");
+        assert_eq!(config.host.as_deref(), Some("web1"));
+        assert!(config.placement.is_some());
+
+        // A instância que escolhe o host não herda o placement do serviço
+        let config = resolve("placement: {labels: {role: web}}", "{host: web2}");
+        assert_eq!(config.host.as_deref(), Some("web2"));
+        assert!(config.placement.is_none());
+
+        let config = resolve("host: web1", "{placement: {hosts: [web3]}}");
+        assert!(config.host.is_none());
+        assert_eq!(config.placement.unwrap().hosts, strings(&["web3"]));
+    }
+
+    #[test]
+    fn config_hash_follows_the_env_file_content() {
+        let env_file = std::env::temp_dir().join(format!("ddr-hash-{}.env", std::process::id()));
+        let env_file_path: String = env_file.to_string_lossy().into_owned();
+        let config = resolve(&format!("env_file: [{env_file_path}]"), "{}");
+        let cmd: String = detached_command("api1", &config, "api:1");
+
+        fs::write(&env_file, "A=1\n").unwrap();
+        let first: String = config_hash(&cmd, &config);
+        assert_eq!(config_hash(&cmd, &config), first);
+
+        fs::write(&env_file, "A=2\n").unwrap();
+        let second: String = config_hash(&cmd, &config);
+        fs::remove_file(&env_file).unwrap();
+
+        assert_ne!(first, second);
+        assert_eq!(first.len(), 16);
+        assert_ne!(config_hash(&format!("{cmd} -v x:y"), &config), config_hash(&cmd, &config));
+    }
 }
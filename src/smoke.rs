@@ -0,0 +1,233 @@
+use std::time::{Duration, Instant};
+
+use regex::Regex;
+use reqwest::{blocking::Client, Method};
+use serde_yaml::Mapping;
+use shell_escape::escape;
+use tracing::{error, info, info_span, warn};
+
+use crate::{
+    hosts::Sessions,
+    instances::declared_instances,
+    models::{SmokeFailure, SmokeTest},
+    redact::redact,
+    remotecheck::check_response,
+    report::{SmokeOutcome, SmokeTestReport},
+    utils::run_remote_output,
+};
+
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+
+/// Um smoke test já validado, com os hosts onde roda resolvidos
+struct Check {
+    name: String,
+    kind: CheckKind,
+    expect: Option<Regex>,
+    timeout: Duration,
+    on_failure: SmokeFailure,
+}
+
+
+enum CheckKind {
+    Http {
+        client: Client,
+        method: Method,
+        url: String,
+        headers: Vec<(String, String)>,
+        data: Option<String>,
+        status: Option<u16>,
+    },
+    Command {
+        hosts: Vec<String>,
+        command: String,
+    },
+    Exec {
+        hosts: Vec<String>,
+        instance: String,
+        command: String,
+    },
+}
+
+
+/// Executa os smoke tests do grupo, registrando o resultado de cada um.
+/// Retorna a ação mais severa pedida pelos testes que falharam, ignorando
+/// os de `on_failure: warn`.
+pub fn run_smoke_tests(
+    sessions: &mut Sessions,
+    group_name: &str,
+    deploy_map: &Mapping,
+    tests: &[SmokeTest],
+    records: &mut Vec<SmokeTestReport>,
+    dry_run: bool,
+) -> anyhow::Result<Option<SmokeFailure>> {
+
+    // Erros de configuração aparecem antes de qualquer teste, inclusive no dry-run
+    let checks: Vec<Check> = tests
+        .iter()
+        .map(|test| Check::new(test, sessions, deploy_map))
+        .collect::<anyhow::Result<_>>()?;
+
+    let mut worst: Option<SmokeFailure> = None;
+    for check in checks {
+        if dry_run {
+            info!("Executaria o smoke test {}", check.name);
+            continue;
+        }
+
+        let _entered = info_span!("smoke_test", test = %check.name).entered();
+        let started = Instant::now();
+        let result = check.run(sessions);
+        let seconds: f64 = started.elapsed().as_secs_f64();
+
+        let (status, error) = match result {
+            Ok(()) => {
+                info!(seconds, "Smoke test {} ok", check.name);
+                (SmokeOutcome::Passed, None)
+            }
+            Err(err) => {
+                let message: String = redact(&format!("{err:#}"));
+                let status = if check.on_failure == SmokeFailure::Warn {
+                    warn!(seconds, "Smoke test {} falhou: {message}", check.name);
+                    SmokeOutcome::Warned
+                } else {
+                    error!(seconds, "Smoke test {} falhou: {message}", check.name);
+                    worst = worst.max(Some(check.on_failure));
+                    SmokeOutcome::Failed
+                };
+                (status, Some(message))
+            }
+        };
+
+        records.push(SmokeTestReport {
+            group: group_name.to_owned(),
+            name: check.name,
+            status,
+            seconds,
+            error,
+        });
+    }
+
+    Ok(worst)
+}
+
+
+impl Check {
+    fn new(test: &SmokeTest, sessions: &Sessions, deploy_map: &Mapping) -> anyhow::Result<Self> {
+        let name: String = test.name
+            .clone()
+            .or_else(|| test.http.clone())
+            .or_else(|| test.command.clone())
+            .or_else(|| test.exec.clone())
+            .unwrap_or_default();
+
+        let timeout: Duration = test.timeout.unwrap_or(DEFAULT_TIMEOUT);
+        let expect: Option<Regex> = test.expect
+            .as_deref()
+            .map(Regex::new)
+            .transpose()
+            .map_err(|err| anyhow::anyhow!("Regex expect inválida no smoke test {name}: {err}"))?;
+
+        let kind = match (&test.http, &test.command, &test.exec) {
+            (Some(url), None, None) => {
+                let method: Method = test.method
+                    .as_deref()
+                    .unwrap_or("GET")
+                    .to_uppercase()
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Método HTTP inválido no smoke test {name}"))?;
+
+                CheckKind::Http {
+                    client: Client::builder()
+                        .timeout(timeout)
+                        .danger_accept_invalid_certs(test.insecure.unwrap_or(false))
+                        .build()?,
+                    method,
+                    url: url.clone(),
+                    headers: test.headers
+                        .iter()
+                        .flatten()
+                        .map(|(name, value)| (name.clone(), value.clone()))
+                        .collect(),
+                    data: test.data.clone(),
+                    status: test.status,
+                }
+            }
+            // Sem `host:`, usa o host padrão (ou o único host do inventário)
+            (None, Some(command), None) => CheckKind::Command {
+                hosts: sessions.inventory().resolve_hosts(&name, test.host.as_deref(), None)?,
+                command: command.clone(),
+            },
+            (None, None, Some(command)) => {
+                let instance_name: &str = test.instance.as_deref().ok_or_else(|| {
+                    anyhow::anyhow!("O smoke test {name} usa exec e precisa de instance")
+                })?;
+                let instance = declared_instances(deploy_map, None)?
+                    .into_iter()
+                    .find(|instance| instance.instance_name == instance_name)
+                    .ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "Instância {instance_name} do smoke test {name} não encontrada"
+                        )
+                    })?;
+
+                CheckKind::Exec {
+                    hosts: sessions.inventory().hosts_for(&instance)?,
+                    instance: instance_name.to_owned(),
+                    command: command.clone(),
+                }
+            }
+            _ => anyhow::bail!(
+                "O smoke test {name} precisa de um, e só um, de http, command e exec"
+            ),
+        };
+
+        Ok(Check { name, kind, expect, timeout, on_failure: test.on_failure })
+    }
+
+    fn run(&self, sessions: &mut Sessions) -> anyhow::Result<()> {
+        // `timeout` só aceita segundos inteiros aqui
+        let seconds: u64 = self.timeout.as_secs().max(1);
+
+        let (hosts, command) = match &self.kind {
+            CheckKind::Http { client, method, url, headers, data, status } => {
+                let mut request = client.request(method.clone(), url);
+                for (name, value) in headers {
+                    request = request.header(name, value);
+                }
+                if let Some(data) = data {
+                    request = request.body(data.clone());
+                }
+
+                let response = request.send()?;
+                let code: u16 = response.status().as_u16();
+                let text: String = response.text()?;
+                return check_response(code, &text, *status, self.expect.as_ref())
+                    .map_err(|failure| anyhow::anyhow!("{url}: {failure}"));
+            }
+            CheckKind::Command { hosts, command } => (
+                hosts,
+                format!("timeout {seconds} sh -c {}", escape(command.as_str().into())),
+            ),
+            CheckKind::Exec { hosts, instance, command } => (
+                hosts,
+                format!(
+                    "timeout {seconds} docker exec {} sh -c {}",
+                    escape(instance.as_str().into()),
+                    escape(command.as_str().into())
+                ),
+            ),
+        };
+
+        for host in hosts {
+            let output: String = run_remote_output(sessions.get(host)?, &command)?;
+            if let Some(ref expect) = self.expect
+                && !expect.is_match(&output) {
+                anyhow::bail!("a saída em `{host}` não contém `{expect}`");
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,247 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+use serde_yaml::Mapping;
+
+use crate::{
+    backend::Backend,
+    hosts::Inventory,
+    instances::{declared_instances, DeclaredInstance},
+    models::OutputFormat,
+    utils::run_remote_output,
+};
+
+
+/// Uma linha de `docker ps -a --format '{{json .}}'`
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PsEntry {
+    names: String,
+    image: String,
+    state: String,
+    status: String,
+}
+
+
+#[derive(Debug, Serialize)]
+struct InstanceStatus {
+    host: String,
+    group: String,
+    service: String,
+    instance: String,
+    exists: bool,
+    state: Option<String>,
+    uptime: Option<String>,
+    expected_image: String,
+    image: Option<String>,
+    image_matches: bool,
+    health: Option<String>,
+    restart_count: Option<u64>,
+}
+
+
+#[derive(Debug, Serialize)]
+struct DriftContainer {
+    host: String,
+    name: String,
+    image: String,
+    state: String,
+}
+
+
+#[derive(Debug, Serialize)]
+struct StatusReport {
+    instances: Vec<InstanceStatus>,
+    drift: Vec<DriftContainer>,
+}
+
+
+pub fn handle_status(
+    inventory: &Inventory,
+    deploy_map: &Mapping,
+    group_name: Option<&str>,
+    format: OutputFormat,
+) -> anyhow::Result<()> {
+
+    let declared: Vec<DeclaredInstance> = declared_instances(deploy_map, group_name)?;
+
+    // A deriva considera todas as instâncias declaradas, não só as do grupo
+    let mut declared_names: HashSet<(String, String)> = HashSet::new();
+    for instance in declared_instances(deploy_map, None)? {
+        for host in inventory.hosts_for(&instance)? {
+            declared_names.insert((host, instance.instance_name.clone()));
+        }
+    }
+
+    let mut instances: Vec<InstanceStatus> = Vec::new();
+    let mut drift: Vec<DriftContainer> = Vec::new();
+
+    for host in inventory.names() {
+        let session: Box<dyn Backend> = inventory.connect(&host)?;
+        let containers: HashMap<String, PsEntry> = list_containers(session.as_ref())?;
+        let details: HashMap<String, (u64, Option<String>)> = inspect_containers(session.as_ref())?;
+
+        for instance in &declared {
+            if !inventory.hosts_for(instance)?.contains(&host) {
+                continue;
+            }
+
+            let container = containers.get(&instance.instance_name);
+            let detail = details.get(&instance.instance_name);
+
+            instances.push(InstanceStatus {
+                host: host.clone(),
+                group: instance.group_name.clone(),
+                service: instance.service_name.clone(),
+                instance: instance.instance_name.clone(),
+                exists: container.is_some(),
+                state: container.map(|c| c.state.clone()),
+                uptime: container.and_then(uptime),
+                expected_image: instance.image_name.clone(),
+                image: container.map(|c| c.image.clone()),
+                image_matches: container.is_some_and(|c| c.image == instance.image_name),
+                health: detail.and_then(|(_, health)| health.clone()),
+                restart_count: detail.map(|(count, _)| *count),
+            });
+        }
+
+        let mut host_drift: Vec<DriftContainer> = containers
+            .into_values()
+            .filter(|container| !declared_names.contains(&(host.clone(), container.names.clone())))
+            .map(|container| DriftContainer {
+                host: host.clone(),
+                name: container.names,
+                image: container.image,
+                state: container.state,
+            })
+            .collect();
+        host_drift.sort_by(|a, b| a.name.cmp(&b.name));
+        drift.extend(host_drift);
+    }
+
+    let report = StatusReport { instances, drift };
+
+    match format {
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+        OutputFormat::Table => print_table(&report),
+    }
+
+    Ok(())
+}
+
+
+fn list_containers(session: &dyn Backend) -> anyhow::Result<HashMap<String, PsEntry>> {
+    let output = run_remote_output(session, "docker ps -a --format '{{json .}}'")?;
+
+    let mut containers: HashMap<String, PsEntry> = HashMap::new();
+    for line in output.lines().filter(|line| !line.trim().is_empty()) {
+        let entry: PsEntry = serde_json::from_str(line)?;
+        containers.insert(entry.names.clone(), entry);
+    }
+
+    Ok(containers)
+}
+
+
+/// Número de restarts e estado do healthcheck de cada container
+fn inspect_containers(
+    session: &dyn Backend
+) -> anyhow::Result<HashMap<String, (u64, Option<String>)>> {
+    let output = run_remote_output(
+        session,
+        "docker ps -aq | xargs -r docker inspect --format \
+         '{{.Name}} {{.RestartCount}} {{if .State.Health}}{{.State.Health.Status}}{{end}}'"
+    )?;
+
+    let mut details: HashMap<String, (u64, Option<String>)> = HashMap::new();
+    for line in output.lines() {
+        let mut parts = line.split_whitespace();
+        let (Some(name), Some(restarts)) = (parts.next(), parts.next()) else { continue };
+        let health = parts.next().map(str::to_owned);
+
+        details.insert(
+            name.trim_start_matches('/').to_owned(),
+            (restarts.parse().unwrap_or(0), health),
+        );
+    }
+
+    Ok(details)
+}
+
+
+/// Extrai o tempo em execução do campo `Status` ("Up 3 hours (healthy)")
+fn uptime(container: &PsEntry) -> Option<String> {
+    let uptime = container.status.strip_prefix("Up ")?;
+    let uptime = match uptime.find(" (") {
+        Some(index) => &uptime[..index],
+        None => uptime,
+    };
+
+    Some(uptime.to_owned())
+}
+
+
+fn print_table(report: &StatusReport) {
+    let header = [
+        "HOST", "GRUPO", "SERVIÇO", "INSTÂNCIA", "ESTADO", "UPTIME", "IMAGEM", "SAÚDE", "RESTARTS",
+    ];
+
+    let rows: Vec<[String; 9]> = report.instances
+        .iter()
+        .map(|status| {
+            let image = match (&status.image, status.image_matches) {
+                (None, _) => "-".to_owned(),
+                (Some(image), true) => image.clone(),
+                (Some(image), false) => format!("{image} (esperado {})", status.expected_image),
+            };
+
+            [
+                status.host.clone(),
+                status.group.clone(),
+                status.service.clone(),
+                status.instance.clone(),
+                status.state.clone().unwrap_or_else(|| "ausente".to_owned()),
+                status.uptime.clone().unwrap_or_else(|| "-".to_owned()),
+                image,
+                status.health.clone().unwrap_or_else(|| "-".to_owned()),
+                status.restart_count.map_or_else(|| "-".to_owned(), |count| count.to_string()),
+            ]
+        })
+        .collect();
+
+    let mut widths: [usize; 9] = header.map(|column| column.chars().count());
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    print_row(&header.map(str::to_owned), &widths);
+    for row in &rows {
+        print_row(row, &widths);
+    }
+
+    if !report.drift.is_empty() {
+        println!("\nContainers não declarados no arquivo de deploy (deriva):");
+        for container in &report.drift {
+            println!(
+                "  {} em {} ({}, {})",
+                container.name,
+                container.host,
+                container.image,
+                container.state
+            );
+        }
+    }
+}
+
+
+fn print_row(row: &[String; 9], widths: &[usize; 9]) {
+    let line: Vec<String> = row
+        .iter()
+        .zip(widths)
+        .map(|(cell, width)| format!("{cell:<width$}"))
+        .collect();
+
+    println!("{}", line.join("  ").trim_end());
+}
@@ -0,0 +1,197 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs,
+    io::Read,
+    os::unix::fs::PermissionsExt,
+    path::{Component, Path, PathBuf},
+};
+
+use sha2::{Digest, Sha256};
+use shell_escape::escape;
+use tracing::debug;
+
+use crate::{
+    backend::Backend,
+    models::ContainerConfig,
+    utils::{matches_any, run_remote, run_remote_output, scp_send},
+};
+
+
+/// Um arquivo local a ser enviado para o diretório remoto
+struct LocalFile {
+    /// Caminho relativo ao diretório atual e ao `DIR` remoto
+    path: PathBuf,
+    mode: u32,
+    hash: String,
+}
+
+
+/// Caminhos locais usados pela instância: os `env_file` relativos e as
+/// origens de bind mounts relativas (`./conf:/etc/conf`), exceto os marcados
+/// em `remote_only`. Caminhos que não existem localmente são ignorados: eles
+/// já devem estar no host remoto.
+pub fn local_sources(container_config: &ContainerConfig) -> anyhow::Result<Vec<PathBuf>> {
+    let mut sources: Vec<&str> = Vec::new();
+
+    for env_file in container_config.env_file.iter().flatten() {
+        if !Path::new(env_file).is_absolute() {
+            sources.push(env_file);
+        }
+    }
+
+    for volume in container_config.volumes.iter().flatten() {
+        let source: &str = volume.split(':').next().unwrap_or_default();
+        if source.starts_with("./") || source.starts_with("../") {
+            sources.push(source);
+        }
+    }
+
+    let remote_only: &[String] = container_config.remote_only.as_deref().unwrap_or_default();
+
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for source in sources {
+        let path: PathBuf = normalize(source);
+        if matches_any(remote_only, &path.to_string_lossy())? || matches_any(remote_only, source)? {
+            continue;
+        }
+        if path.exists() && !paths.contains(&path) {
+            paths.push(path);
+        }
+    }
+
+    Ok(paths)
+}
+
+
+/// Envia os caminhos locais para `from_dir` no host, mantendo a estrutura de
+/// diretórios e as permissões. Arquivos com o mesmo conteúdo no host não são
+/// enviados de novo.
+pub fn sync_sources(
+    session: &dyn Backend,
+    from_dir: &str,
+    sources: &[PathBuf],
+) -> anyhow::Result<()> {
+    let mut files: Vec<LocalFile> = Vec::new();
+    for source in sources {
+        collect_files(source, &mut files)?;
+    }
+
+    if files.is_empty() {
+        return Ok(());
+    }
+
+    let from_dir: &str = from_dir.trim_end_matches('/');
+    let remote_path = |path: &Path| format!("{from_dir}/{}", path.display());
+
+    let remote_paths: Vec<String> = files
+        .iter()
+        .map(|file| escape(remote_path(&file.path).into()).into_owned())
+        .collect();
+
+    let remote_hashes: HashMap<String, String> = parse_listing(&run_remote_output(
+        session,
+        &format!("sha256sum -- {} 2>/dev/null || true", remote_paths.join(" ")),
+    )?);
+    let remote_modes: HashMap<String, String> = parse_listing(&run_remote_output(
+        session,
+        &format!("stat -c '%a %n' -- {} 2>/dev/null || true", remote_paths.join(" ")),
+    )?);
+
+    let directories: Vec<String> = files
+        .iter()
+        .filter_map(|file| file.path.parent())
+        .map(|parent| escape(remote_path(parent).into()).into_owned())
+        .collect::<BTreeSet<String>>()
+        .into_iter()
+        .collect();
+    run_remote(session, &format!("mkdir -p {}", directories.join(" ")))?;
+
+    // Arquivos que precisam de chmod, agrupados pela permissão
+    let mut chmods: BTreeMap<u32, Vec<String>> = BTreeMap::new();
+
+    for file in &files {
+        let remote: String = remote_path(&file.path);
+        let mode: String = format!("{:o}", file.mode);
+
+        if remote_hashes.get(&remote) == Some(&file.hash) {
+            if remote_modes.get(&remote) != Some(&mode) {
+                chmods.entry(file.mode).or_default().push(escape(remote.into()).into_owned());
+            } else {
+                debug!("{} sem alterações", file.path.display());
+            }
+            continue;
+        }
+
+        scp_send(session, &file.path.to_string_lossy(), &remote, file.mode as i32)?;
+        // O scp não altera a permissão de arquivos que já existiam
+        chmods.entry(file.mode).or_default().push(escape(remote.into()).into_owned());
+    }
+
+    for (mode, paths) in chmods {
+        run_remote(session, &format!("chmod {mode:o} {}", paths.join(" ")))?;
+    }
+
+    Ok(())
+}
+
+
+/// Adiciona o arquivo ou, se for diretório, todos os arquivos dentro dele
+fn collect_files(path: &Path, files: &mut Vec<LocalFile>) -> anyhow::Result<()> {
+    let metadata = fs::metadata(path)?;
+
+    if metadata.is_dir() {
+        let mut entries: Vec<PathBuf> = fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<_, _>>()?;
+        entries.sort();
+
+        for entry in entries {
+            collect_files(&normalize(&entry.to_string_lossy()), files)?;
+        }
+        return Ok(());
+    }
+
+    // Um arquivo pode vir de mais de uma origem (diretório e arquivo dentro dele)
+    if files.iter().any(|file| file.path == path) {
+        return Ok(());
+    }
+
+    let mut hasher = Sha256::new();
+    let mut file = fs::File::open(path)?;
+    let mut buffer = [0u8; 8192];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    files.push(LocalFile {
+        path: path.to_path_buf(),
+        mode: metadata.permissions().mode() & 0o7777,
+        hash: format!("{:x}", hasher.finalize()),
+    });
+
+    Ok(())
+}
+
+
+/// Lê linhas `<valor> <caminho>` (saída de sha256sum e stat) em um mapa
+/// caminho -> valor
+fn parse_listing(output: &str) -> HashMap<String, String> {
+    output
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .map(|(value, path)| (path.trim_start().to_owned(), value.to_owned()))
+        .collect()
+}
+
+
+/// Remove os `./` do caminho
+fn normalize(path: &str) -> PathBuf {
+    Path::new(path)
+        .components()
+        .filter(|component| *component != Component::CurDir)
+        .collect()
+}
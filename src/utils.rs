@@ -1,16 +1,25 @@
 use std::fs;
-use std::io::Read;
+use std::io::Write;
 
 use dotenvy::from_filename;
-use regex::Regex;
-use serde_yaml::{Value, from_str};
+use glob::Pattern;
+use serde_yaml::Value;
 use ssh2::Session;
 use std::collections::HashMap;
 use std::env;
 use std::net::TcpStream;
 use std::path::Path;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
+use tracing::{debug, info};
+
+use crate::backend::Backend;
 use crate::models::SSHConfig;
+use crate::instances::validate_services;
+use crate::interpolation::{interpolate, parse_variables};
+use crate::overlay::load_merged;
+use crate::replicas::expand_replicas;
+use crate::report::Transfer;
 
 pub fn docker_save(image: &str, output_file: &str) -> anyhow::Result<()> {
     let status = std::process::Command::new("docker")
@@ -27,97 +36,137 @@ pub fn docker_save(image: &str, output_file: &str) -> anyhow::Result<()> {
     Ok(())
 }
 
-pub fn scp_send(local_file: &str, remote_path: &str, ssh_config: &SSHConfig) -> anyhow::Result<()> {
-    println!("Enviando o arquivo {local_file}");
-    let session = get_session(ssh_config)?;
+pub fn scp_send(
+    session: &dyn Backend,
+    local_file: &str,
+    remote_path: &str,
+    mode: i32,
+) -> anyhow::Result<Transfer> {
+    debug!("Enviando o arquivo {local_file}");
+    let started = Instant::now();
+    let size: u64 = fs::metadata(local_file)?.len();
+
+    let mut file = fs::File::open(local_file)?;
+    session.write(remote_path, &mut file, size, mode)?;
+    let transfer = Transfer { bytes: size, seconds: started.elapsed().as_secs_f64() };
+    info!(
+        bytes = transfer.bytes,
+        seconds = transfer.seconds,
+        "Arquivo {local_file} enviado"
+    );
+    Ok(transfer)
+}
 
-    let mut remote_file = session.scp_send(
-        Path::new(remote_path),
-        0o644,
-        fs::metadata(local_file)?.len(),
-        None,
-    )?;
+/// Grava um conteúdo em memória no host remoto. O conteúdo não aparece em
+/// nenhum comando nem na saída.
+pub fn scp_write(
+    session: &dyn Backend,
+    remote_path: &str,
+    content: &[u8],
+    mode: i32,
+) -> anyhow::Result<()> {
+    session.write(remote_path, &mut &content[..], content.len() as u64, mode)
+}
 
-    let mut local_file = fs::File::open(local_file)?;
-    std::io::copy(&mut local_file, &mut remote_file)?;
-    remote_file.send_eof()?;
-    remote_file.wait_eof()?;
-    remote_file.close()?;
-    remote_file.wait_close()?;
-    println!("Enviado!");
-    Ok(())
+/// Nome do contêiner anterior de uma instância, guardado parado enquanto o
+/// deploy do grupo pode ser revertido
+pub fn previous_container(container_name: &str) -> String {
+    format!("{container_name}_ddr_previous")
+}
+
+pub fn docker_load(session: &dyn Backend, remote_file: &str) -> anyhow::Result<()> {
+    debug!("Docker load: {remote_file}");
+    run_remote(session, &format!("docker load -i {}", remote_file))
 }
 
-pub fn docker_load_and_run(
-    session: &Session,
-    remote_file: &str,
+/// Recria o contêiner com `cmd`. Com `keep_previous`, o contêiner atual é
+/// parado e renomeado em vez de removido, para um possível rollback.
+pub fn docker_replace(
+    session: &dyn Backend,
     cmd: String,
     container_name: &str,
     ssh_config: &SSHConfig,
+    keep_previous: bool,
 ) -> anyhow::Result<()> {
-    println!("Docker load and run: {remote_file}");
-
-    run_remote(session, &format!("docker load -i {}", remote_file))?;
-    run_remote(session, &format!("docker rm -f {} || true", container_name))?;
+    if keep_previous {
+        let previous: String = previous_container(container_name);
+        run_remote(session, &format!("docker rm -f {previous} || true"))?;
+        run_remote(
+            session,
+            &format!(
+                "if docker inspect {container_name} >/dev/null 2>&1; then \
+                 docker stop {container_name} && docker rename {container_name} {previous}; fi"
+            ),
+        )?;
+    } else {
+        run_remote(session, &format!("docker rm -f {} || true", container_name))?;
+    }
     run_remote(session, &format!("cd {} && {}", ssh_config.from_dir, cmd))?;
 
     Ok(())
 }
 
-pub fn remove_local_and_remote_file(session: &Session, remote_file: &str) -> anyhow::Result<()> {
-    println!("Removendo arquivo local e remoto {remote_file}");
-    run_remote(session, &format!("rm -f {}", remote_file))?;
-    std::fs::remove_file(remote_file).ok();
+/// Remove o contêiner novo e volta a rodar o anterior, se houver um
+pub fn docker_rollback(session: &dyn Backend, container_name: &str) -> anyhow::Result<()> {
+    let previous: String = previous_container(container_name);
+    run_remote(session, &format!("docker rm -f {container_name} || true"))?;
+    run_remote(
+        session,
+        &format!(
+            "if docker inspect {previous} >/dev/null 2>&1; then \
+             docker rename {previous} {container_name} && docker start {container_name}; fi"
+        ),
+    )?;
 
     Ok(())
 }
 
-pub fn docker_run(session: &Session, cmd: String) -> anyhow::Result<()> {
-    run_remote(session, &format!("{cmd}"))?;
-    Ok(())
+/// Remove o contêiner anterior guardado para rollback
+pub fn discard_previous(session: &dyn Backend, container_name: &str) -> anyhow::Result<()> {
+    run_remote(
+        session,
+        &format!("docker rm -f {} || true", previous_container(container_name)),
+    )
 }
 
-pub fn run_remote(session: &ssh2::Session, command: &str) -> anyhow::Result<()> {
-    println!("Executando comando remoto:");
-    dbg!(command);
-
-    let mut channel = session.channel_session()?;
-    channel.exec(command)?;
+pub fn remove_remote_file(session: &dyn Backend, remote_file: &str) -> anyhow::Result<()> {
+    debug!("Removendo arquivo remoto {remote_file}");
+    run_remote(session, &format!("rm -f {}", remote_file))?;
 
-    // stdout
-    let mut stdout = Vec::new();
-    channel.read_to_end(&mut stdout)?;
-    let stdout_str = String::from_utf8_lossy(&stdout);
-    if !stdout_str.trim().is_empty() {
-        println!("[remote stdout] {}", stdout_str);
-    }
+    Ok(())
+}
 
-    // stderr
-    let mut stderr = Vec::new();
-    channel.stderr().read_to_end(&mut stderr)?;
-    let stderr_str = String::from_utf8_lossy(&stderr);
-    if !stderr_str.trim().is_empty() {
-        eprintln!("[remote stderr] {}", stderr_str);
-    }
+pub fn docker_run(session: &dyn Backend, cmd: String) -> anyhow::Result<()> {
+    run_remote(session, &cmd)?;
+    Ok(())
+}
 
-    channel.wait_close()?;
-    let exit_status = channel.exit_status()?;
-    if exit_status != 0 {
-        anyhow::bail!("Comando remoto falhou ({exit_status}): {command}");
-    }
+pub fn run_remote(session: &dyn Backend, command: &str) -> anyhow::Result<()> {
+    session.run(command)
+}
 
-    Ok(())
+/// Executa um comando remoto sem ecoá-lo e devolve o stdout
+pub fn run_remote_output(session: &dyn Backend, command: &str) -> anyhow::Result<String> {
+    session.output(command)
 }
 
 pub fn get_session(ssh_config: &SSHConfig) -> anyhow::Result<Session> {
     // Conexão TCP
-    let tcp = TcpStream::connect(format!("{}:22", ssh_config.host))?;
+    let tcp = TcpStream::connect(format!("{}:{}", ssh_config.host, ssh_config.port))?;
     let mut session = Session::new()?;
     session.set_tcp_stream(tcp);
     session.handshake()?;
 
     // Autenticação
-    session.userauth_password(&ssh_config.user, &ssh_config.password)?;
+    match ssh_config.key_file {
+        Some(ref key_file) => session.userauth_pubkey_file(
+            &ssh_config.user,
+            None,
+            Path::new(key_file),
+            None,
+        )?,
+        None => session.userauth_password(&ssh_config.user, &ssh_config.password)?,
+    }
 
     Ok(session)
 }
@@ -134,54 +183,51 @@ pub fn get_ssh_config(filename: &str) -> anyhow::Result<SSHConfig> {
     Ok(SSHConfig::new(user, host, password, from_dir))
 }
 
-pub fn parse_variables(yaml_content: &str) -> anyhow::Result<HashMap<String, String>> {
-    let raw_yaml: Value = from_str(yaml_content)?;
-
-    // Se não tiver `define`, retorna HashMap vazio
-    let define_values = match raw_yaml.get("define").and_then(Value::as_mapping) {
-        Some(values) => values,
-        None => return Ok(HashMap::new()),
-    };
-
-    let mut variables = HashMap::new();
-    for (key, value) in define_values {
-        if let (Some(k), Some(v)) = (key.as_str(), value.as_str()) {
-            variables.insert(k.to_string(), v.to_string());
-        }
-    }
+/// Sufixo para nomes de contêineres avulsos que não colidem entre execuções
+pub fn unique_suffix() -> String {
+    let millis: u128 = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis())
+        .unwrap_or_default();
 
-    Ok(variables)
+    format!("{}_{millis}", std::process::id())
 }
 
-fn replace_variables(
-    yaml_content: &str,
-    variables: &HashMap<String, String>,
-) -> anyhow::Result<String> {
-    let re = Regex::new(r"\$\{([^}]+)\}").unwrap();
-    let mut replaced_content = yaml_content.to_string();
+/// Pergunta ao usuário e retorna se a resposta foi afirmativa
+pub fn confirm(question: &str) -> anyhow::Result<bool> {
+    print!("{question} [s/N] ");
+    std::io::stdout().flush()?;
+
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
 
-    for captures in re.captures_iter(yaml_content) {
-        let full_match = captures.get(0).unwrap().as_str();
-        let var_name = captures.get(1).unwrap().as_str();
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "s" | "sim" | "y" | "yes"))
+}
 
-        if let Some(value) = variables.get(var_name) {
-            replaced_content = replaced_content.replace(full_match, value);
-        } else {
-            return Err(anyhow::anyhow!("Variável não encontrada: {}", var_name));
+/// Verifica se `name` casa com algum dos padrões glob informados
+pub fn matches_any(patterns: &[String], name: &str) -> anyhow::Result<bool> {
+    for pattern in patterns {
+        if Pattern::new(pattern)?.matches(name) {
+            return Ok(true);
         }
     }
 
-    Ok(replaced_content)
+    Ok(false)
 }
 
-pub fn process_deployment_file(file_path: &str) -> anyhow::Result<Value> {
-    let original_content = fs::read_to_string(file_path)?;
+pub fn process_deployment_file(file_paths: &[String], env_file: &str) -> anyhow::Result<Value> {
+    // As variáveis do arquivo de ambiente também podem ser interpoladas
+    from_filename(env_file).ok();
+
+    let original_config: Value = load_merged(file_paths)?;
 
-    let variables: HashMap<String, String> = parse_variables(&original_content)?;
+    let variables: HashMap<String, Value> = parse_variables(&original_config);
 
-    let processed_content = replace_variables(&original_content, &variables)?;
+    let in_files = |err: anyhow::Error| err.context(format!("Em {}", file_paths.join(", ")));
 
-    let full_config: Value = from_str(&processed_content)?;
+    let full_config: Value = interpolate(original_config, &variables).map_err(in_files)?;
+    let full_config: Value = expand_replicas(full_config).map_err(in_files)?;
+    validate_services(&full_config).map_err(in_files)?;
 
     Ok(full_config)
 }